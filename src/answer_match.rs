@@ -0,0 +1,160 @@
+use crate::quest::Answer;
+use crate::BotResult;
+use regex::{Regex, RegexBuilder};
+
+#[derive(Debug, PartialEq)]
+pub enum AnswerMatch<'a> {
+    Correct,
+    /// Wrong, but close enough to one of the accepted answers to deserve a hint
+    Close(&'a str),
+    Wrong,
+}
+
+/// Checks messages against the rules of a single answer
+pub struct AnswerMatcher {
+    /// Normalised answer and synonyms
+    accepted: Vec<String>,
+    pattern: Option<Regex>,
+    typos: usize,
+    hint: Option<(String, usize)>,
+}
+
+impl AnswerMatcher {
+    pub fn new(answer: &Answer) -> BotResult<Self> {
+        let accepted = answer
+            .answer
+            .iter()
+            .chain(answer.synonyms.iter())
+            .map(|a| normalize(a))
+            .collect::<Vec<_>>();
+        if accepted.iter().any(|a| a.is_empty()) {
+            return Err(
+                format!("Answer {}: accepted answers must not be empty", answer.name).into(),
+            );
+        }
+        let pattern = match answer.pattern {
+            // The whole message must match, not just a part of it. Messages are normalized,
+            // so the pattern ignores case and treats ё as е to match them the same way.
+            Some(ref pattern) => Some(
+                RegexBuilder::new(&format!("^(?:{})$", pattern.replace(&['ё', 'Ё'][..], "е")))
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("Answer {}: invalid pattern: {}", answer.name, e))?,
+            ),
+            None => None,
+        };
+        if accepted.is_empty() && pattern.is_none() {
+            return Err(format!(
+                "Answer {}: an answer, synonyms or a pattern is required",
+                answer.name
+            )
+            .into());
+        }
+        let hint = answer.hint.as_ref().map(|h| (h.text.clone(), h.distance));
+        Ok(Self {
+            accepted,
+            pattern,
+            typos: answer.typos,
+            hint,
+        })
+    }
+
+    pub fn check(&self, text: &str) -> AnswerMatch<'_> {
+        let text = normalize(text);
+        if self.pattern.as_ref().map_or(false, |p| p.is_match(&text)) {
+            return AnswerMatch::Correct;
+        }
+        let distance = self.accepted.iter().map(|a| edit_distance(a, &text)).min();
+        match (distance, &self.hint) {
+            (Some(d), _) if d <= self.typos => AnswerMatch::Correct,
+            (Some(d), Some((hint, hint_distance))) if d <= *hint_distance => {
+                AnswerMatch::Close(hint)
+            }
+            _ => AnswerMatch::Wrong,
+        }
+    }
+}
+
+/// Lowercases the text, replaces ё with е and collapses whitespace
+pub fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .replace('ё', "е")
+}
+
+/// The number of single-character insertions, deletions and substitutions
+/// needed to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(toml: &str) -> AnswerMatcher {
+        let answer: Answer = toml::from_str(&format!("name = \"riddle\"\n{}", toml)).unwrap();
+        AnswerMatcher::new(&answer).unwrap()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  Ёлка \t в   ЛЕСУ\n"), "елка в лесу");
+        assert_eq!(edit_distance("лосось", "лосос"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_exact_and_synonyms() {
+        let m = matcher("answer = \"Ёж\"\nsynonyms = [\"ежик\", \"ёжик\"]");
+        assert_eq!(m.check("еж"), AnswerMatch::Correct);
+        assert_eq!(m.check("  ЁЖИК "), AnswerMatch::Correct);
+        assert_eq!(m.check("это еж"), AnswerMatch::Wrong);
+
+        // pasting a long string no longer passes
+        let m = matcher("answer = \"679823154\"");
+        assert_eq!(m.check("679823154"), AnswerMatch::Correct);
+        assert_eq!(m.check("123679823154456"), AnswerMatch::Wrong);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let m = matcher("pattern = \"19[0-9]{2}( год)?\"");
+        assert_eq!(m.check("1961"), AnswerMatch::Correct);
+        assert_eq!(m.check("1961 ГОД"), AnswerMatch::Correct);
+        assert_eq!(m.check("в 1961"), AnswerMatch::Wrong);
+
+        let m = matcher("pattern = \"(Ёлка|Сосна) [0-9]+\"");
+        assert_eq!(m.check("ёлка 3"), AnswerMatch::Correct);
+        assert_eq!(m.check("СОСНА 12"), AnswerMatch::Correct);
+        assert_eq!(m.check("ель 3"), AnswerMatch::Wrong);
+
+        let answer: Answer = toml::from_str("name = \"riddle\"\npattern = \"(\"").unwrap();
+        assert!(AnswerMatcher::new(&answer).is_err());
+        let answer: Answer = toml::from_str("name = \"riddle\"").unwrap();
+        assert!(AnswerMatcher::new(&answer).is_err());
+    }
+
+    #[test]
+    fn test_typos_and_hint() {
+        let m =
+            matcher("answer = \"лосось\"\ntypos = 1\nhint = { text = \"почти!\", distance = 3 }");
+        assert_eq!(m.check("лосос"), AnswerMatch::Correct);
+        assert_eq!(m.check("лоси"), AnswerMatch::Close("почти!"));
+        assert_eq!(m.check("форель"), AnswerMatch::Wrong);
+    }
+}
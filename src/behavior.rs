@@ -1,18 +1,37 @@
-use crate::vkapi::{Client, VkApi, VkMessage};
+use crate::vkapi::{Client, VkApi, VkMessage, VkMessageEvent, VkMessagesApi};
+use crate::{MSG_DELAY_FAIL, MSG_DELAY_SUCCESS};
+use std::time::Duration;
 
-mod chest;
-pub use chest::ChestBehavior;
-mod gates;
-pub use gates::GatesBehavior;
+mod challenge;
+pub use challenge::ChallengeBehavior;
 mod stats;
 pub use stats::StatsBehavior;
-mod stone;
-pub use stone::StoneBehavior;
 mod test;
 pub use test::TestBehavior;
 
-pub type ThreadResult<'e> = Result<(), Box<dyn std::error::Error + 'e>>;
+pub type ThreadResult = crate::BotResult<()>;
+
+/// Pauses before replies; wrong guesses are answered slowly to discourage brute-forcing
+#[derive(Clone, Copy, Debug)]
+pub struct ReplyDelays {
+    pub fail: Duration,
+    pub success: Duration,
+}
+
+impl Default for ReplyDelays {
+    fn default() -> Self {
+        Self {
+            fail: MSG_DELAY_FAIL,
+            success: MSG_DELAY_SUCCESS,
+        }
+    }
+}
 
 pub trait Behavior<C: Client>: Send + Sync + std::fmt::Display {
-    fn process_on_own_thread<'s>(&'s self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult<'s>;
+    fn process_on_own_thread(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult;
+
+    /// Handles a callback button press, which must be answered with `send_event_answer`
+    fn process_event_on_own_thread(&self, vk: &VkApi<C>, event: &VkMessageEvent) -> ThreadResult {
+        vk.send_event_answer(event, None)
+    }
 }
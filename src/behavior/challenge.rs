@@ -0,0 +1,750 @@
+use crate::answer_match::{AnswerMatch, AnswerMatcher};
+use crate::behavior::{Behavior, ReplyDelays, ThreadResult};
+use crate::img_match::ImageMatcher;
+use crate::quest::{Challenge, Location, Stage};
+use crate::storage::{PlayerSet, Storage};
+use crate::template::{Template, TemplateValue, TemplateVars};
+use crate::vkapi::{
+    Client, VkApi, VkAttachment, VkButtonColor, VkEventAction, VkKeyboard, VkMessage,
+    VkMessageEvent, VkMessagesApi, VkPhotosApi, VkUsersApi,
+};
+use crate::BotResult;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+mod admin;
+use admin::{AdminAct, ChallengeAdmin};
+
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+const HINT_COMMAND: &str = "hint";
+
+/// Runs a challenge defined in the quest file, moving players through its stages
+pub struct ChallengeBehavior {
+    id: String,
+    challenge: Challenge,
+    /// Targets are tagged with their stage and check index
+    matcher: ImageMatcher<(usize, usize)>,
+    /// Matchers for the answers of each stage
+    answers: Vec<Vec<AnswerMatcher>>,
+    storage: Storage,
+    admin_ids: Vec<i64>,
+    /// Pending admin actions, by admin id
+    admin_acts: Mutex<HashMap<i64, AdminAct>>,
+    delays: ReplyDelays,
+}
+
+impl ChallengeBehavior {
+    pub fn new(
+        id: &str,
+        challenge: Challenge,
+        storage: Storage,
+        admin_ids: Vec<i64>,
+    ) -> BotResult<Self> {
+        let mut matcher = ImageMatcher::new();
+        let mut answers = Vec::with_capacity(challenge.stages.len());
+        for (stage, stage_def) in challenge.stages.iter().enumerate() {
+            for (check, target) in stage_def.targets.iter().enumerate() {
+                matcher.add_target(target, (stage, check))?;
+            }
+            answers.push(
+                stage_def
+                    .answers
+                    .iter()
+                    .map(AnswerMatcher::new)
+                    .collect::<BotResult<Vec<_>>>()?,
+            );
+        }
+        Ok(Self {
+            id: id.to_owned(),
+            challenge,
+            matcher,
+            answers,
+            storage,
+            admin_ids,
+            admin_acts: Mutex::new(HashMap::new()),
+            delays: ReplyDelays::default(),
+        })
+    }
+
+    /// Fills in the variables of a stage reply, see `STAGE_TEMPLATE_VARIABLES`
+    fn render<C: Client>(
+        &self,
+        vk: &VkApi<C>,
+        msg: &VkMessage,
+        source: &str,
+        stage: usize,
+        passed: usize,
+    ) -> BotResult<String> {
+        let template = Template::parse(source)?;
+        let required = self.challenge.stages[stage].required();
+        let mut vars = TemplateVars::new();
+        // The player is only looked up when their name is used
+        if template.variables().any(|v| v == "name") {
+            let name = vk
+                .get_user(&msg.from_id.to_string())?
+                .map(|user| user.first_name)
+                .unwrap_or_default();
+            vars.insert("name", TemplateValue::Text(name));
+        }
+        vars.insert("stage", TemplateValue::Number(stage + 1));
+        vars.insert("stages", TemplateValue::Number(self.challenge.stages.len()));
+        vars.insert("passed", TemplateValue::Number(passed));
+        vars.insert("required", TemplateValue::Number(required));
+        vars.insert(
+            "remaining",
+            TemplateValue::Number(required.saturating_sub(passed)),
+        );
+        Ok(template.render(&vars))
+    }
+
+    /// (stage, check index) of every check the message passes, across all stages
+    fn passed_checks<C: Client>(
+        &self,
+        vk: &VkApi<C>,
+        msg: &VkMessage,
+    ) -> BotResult<Vec<(usize, usize)>> {
+        let mut passed = Vec::new();
+        if self.challenge.stages.iter().any(|s| !s.targets.is_empty()) {
+            for att in msg.all_photos() {
+                let image = vk.download_photo(att, self.challenge.image_width)?;
+                let hashes = self.matcher.hash(&image)?;
+                passed.extend(self.matcher.find(&hashes).into_iter().map(|m| *m.value));
+            }
+        }
+        for (stage, stage_def) in self.challenge.stages.iter().enumerate() {
+            let offset = stage_def.targets.len();
+            for (i, answer) in self.answers[stage].iter().enumerate() {
+                if answer.check(&msg.text) == AnswerMatch::Correct {
+                    passed.push((stage, offset + i));
+                }
+            }
+            let offset = offset + stage_def.answers.len();
+            for (i, location) in stage_def.locations.iter().enumerate() {
+                // Only the player's own location counts, forwarded messages are ignored
+                if msg.attachments.iter().any(|att| is_within(att, location)) {
+                    passed.push((stage, offset + i));
+                }
+            }
+        }
+        passed.sort();
+        passed.dedup();
+        Ok(passed)
+    }
+}
+
+/// Offers the stage hint to players who have not completed the stage yet
+fn hint_keyboard(stage: &Stage) -> Option<VkKeyboard> {
+    stage
+        .hint
+        .as_ref()
+        .map(|_| VkKeyboard::inline().callback("Подсказка", HINT_COMMAND, VkButtonColor::Secondary))
+}
+
+fn completion_keyboard(stage: &Stage) -> Option<VkKeyboard> {
+    stage
+        .completion_link
+        .as_ref()
+        .map(|link| VkKeyboard::inline().link(&link.label, &link.url))
+}
+
+fn is_within(attachment: &VkAttachment, location: &Location) -> bool {
+    match *attachment {
+        VkAttachment::Geo {
+            latitude,
+            longitude,
+            ..
+        } => {
+            let distance = distance_meters(
+                (latitude, longitude),
+                (location.latitude, location.longitude),
+            );
+            distance <= location.radius
+        }
+        _ => false,
+    }
+}
+
+/// Great-circle distance between two (latitude, longitude) points, in meters
+fn distance_meters(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.1 - a.1).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * h.sqrt().asin()
+}
+
+impl std::fmt::Display for ChallengeBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Challenge {}", self.id)
+    }
+}
+
+impl<C: Client> Behavior<C> for ChallengeBehavior {
+    fn process_on_own_thread(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult {
+        // Admins playing in a group chat with their team are treated as players
+        if self.admin_ids.contains(&msg.from_id) && !msg.is_group_chat() {
+            return self.reply_admin(vk, msg);
+        }
+        // Progress is stored per conversation: private messages come from the player themselves,
+        // and a team playing in a group chat shares its progress. Chat peer ids never collide
+        // with user ids.
+        let player = msg.peer_id;
+        if self
+            .storage
+            .set_contains(&PlayerSet::completed(&self.id), player)?
+        {
+            return Ok(());
+        }
+        let player_stage = self.storage.stage(&self.id, player)?;
+        // Players who completed the stone challenge before the completion set was introduced
+        if player_stage >= self.challenge.stages.len() {
+            return Ok(());
+        }
+        let current_stage = &self.challenge.stages[player_stage];
+        let keyboard = hint_keyboard(current_stage);
+
+        let buckets = current_stage
+            .check_names()
+            .iter()
+            .map(|name| PlayerSet::check_bucket(&self.id, name))
+            .collect::<Vec<_>>();
+
+        let passed = self.passed_checks(vk, msg)?;
+        let passed_other_stage = passed.iter().any(|&(stage, _)| stage != player_stage);
+        let mut passed = passed
+            .into_iter()
+            .filter(|&(stage, _)| stage == player_stage)
+            .map(|(_, check)| check)
+            .collect::<Vec<_>>();
+        if current_stage.ordered {
+            let mut next = 0;
+            while next < buckets.len() && self.storage.set_contains(&buckets[next], player)? {
+                next += 1;
+            }
+            let first = next;
+            while passed.contains(&next) {
+                next += 1;
+            }
+            passed = (first..next).collect();
+        }
+
+        if passed.is_empty() {
+            let hint = self.answers[player_stage]
+                .iter()
+                .filter_map(|answer| match answer.check(&msg.text) {
+                    AnswerMatch::Close(hint) => Some(hint),
+                    _ => None,
+                })
+                .next();
+            if let Some(hint) = hint {
+                std::thread::sleep(self.delays.fail);
+                return vk.reply(msg, hint, None, keyboard.as_ref(), self.challenge.quote);
+            }
+            // Only when nothing from the current stage matched, since the same answer
+            // or image can appear in several stages
+            let fail_text = match current_stage.wrong_stage_text {
+                Some(ref wrong_stage_text) if passed_other_stage => Some(wrong_stage_text),
+                _ => current_stage.fail_text.as_ref(),
+            };
+            if let Some(fail_text) = fail_text {
+                let total_passed =
+                    self.storage
+                        .sets_add_and_count_containing(&[], &buckets, player)?;
+                let reply = self.render(vk, msg, fail_text, player_stage, total_passed)?;
+                std::thread::sleep(self.delays.fail);
+                return vk.reply(msg, &reply, None, keyboard.as_ref(), self.challenge.quote);
+            }
+        }
+        let passed_buckets = passed
+            .into_iter()
+            .map(|check| buckets[check].clone())
+            .collect::<Vec<_>>();
+        let total_passed =
+            self.storage
+                .sets_add_and_count_containing(&passed_buckets, &buckets, player)?;
+        if total_passed >= current_stage.required() {
+            let reply = self.render(
+                vk,
+                msg,
+                &current_stage.completion_text,
+                player_stage,
+                total_passed,
+            )?;
+            std::thread::sleep(self.delays.success);
+
+            let photo = match current_stage.completion_image {
+                Some(ref image) => Some(vk.upload_message_photo(msg.peer_id, image.as_upload())?),
+                None => None,
+            };
+            vk.reply(
+                msg,
+                &reply,
+                photo.as_deref(),
+                completion_keyboard(current_stage).as_ref(),
+                self.challenge.quote,
+            )?;
+
+            self.storage.advance_stage(&self.id, player)?;
+            if player_stage + 1 == self.challenge.stages.len() {
+                self.storage
+                    .set_add(&PlayerSet::completed(&self.id), player)?;
+            }
+        } else {
+            let reply = self.render(
+                vk,
+                msg,
+                current_stage.progress_text(),
+                player_stage,
+                total_passed,
+            )?;
+            std::thread::sleep(self.delays.success);
+            vk.reply(msg, &reply, None, keyboard.as_ref(), self.challenge.quote)?;
+        }
+        Ok(())
+    }
+
+    fn process_event_on_own_thread(&self, vk: &VkApi<C>, event: &VkMessageEvent) -> ThreadResult {
+        let player = event.peer_id;
+        if event.command() != Some(HINT_COMMAND)
+            || self
+                .storage
+                .set_contains(&PlayerSet::completed(&self.id), player)?
+        {
+            return vk.send_event_answer(event, None);
+        }
+        // The button may be pressed on an old message, so the hint is for the current stage
+        let player_stage = self.storage.stage(&self.id, player)?;
+        let hint = self
+            .challenge
+            .stages
+            .get(player_stage)
+            .and_then(|stage| stage.hint.as_ref());
+        let action = hint.map(|text| VkEventAction::ShowSnackbar { text: text.clone() });
+        vk.send_event_answer(event, action.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vkapi::{VkPhoto, VkPhotoSize};
+    use std::time::Duration;
+
+    /// A behavior with in-memory storage that replies without delays
+    fn behavior(id: &str, toml: &str) -> ChallengeBehavior {
+        let challenge = toml::from_str(toml).unwrap();
+        let mut behavior =
+            ChallengeBehavior::new(id, challenge, Storage::in_memory(), vec![]).unwrap();
+        behavior.delays = ReplyDelays {
+            fail: Duration::from_secs(0),
+            success: Duration::from_secs(0),
+        };
+        behavior
+    }
+
+    fn message(text: &str, attachments: Vec<VkAttachment>) -> VkMessage {
+        VkMessage {
+            text: text.into(),
+            from_id: 1010,
+            peer_id: 1010,
+            id: 1,
+            conversation_message_id: 1,
+            date: 0,
+            attachments,
+            forwarded: vec![],
+            reply_to: None,
+            payload: None,
+        }
+    }
+
+    fn photo(name: &str) -> VkAttachment {
+        VkAttachment::Photo(
+            VkPhoto::new(vec![VkPhotoSize {
+                width: 480,
+                url: format!("https://sun9-1.userapi.com/{}", name),
+            }])
+            .unwrap(),
+        )
+    }
+
+    fn geo(latitude: f64, longitude: f64) -> VkAttachment {
+        VkAttachment::Geo {
+            latitude,
+            longitude,
+            place: None,
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        // Palace Square to St. Isaac's Cathedral
+        let distance = distance_meters((59.9398, 30.3146), (59.9343, 30.3061));
+        assert!((distance - 773.0).abs() < 5.0, "{}", distance);
+        assert_eq!(distance_meters((10.0, 20.0), (10.0, 20.0)), 0.0);
+    }
+
+    #[test]
+    fn test_answer() {
+        let vk = VkApi::with_fixture("challenge_answer.json");
+        let gates = behavior(
+            "gates",
+            r#"
+                [[stages]]
+                answers = [
+                    { name = "code", answer = "679823154", hint = { text = "almost", distance = 2 } },
+                ]
+                completion_text = "success"
+                fail_text = "fail"
+                "#,
+        );
+        // "almost"
+        let msg = message("67982315", vec![]);
+        gates.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(!gates
+            .storage
+            .set_contains(&PlayerSet::completed("gates"), 1010)
+            .unwrap());
+        // "success"
+        let msg = message(" 679823154 ", vec![]);
+        gates.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(gates
+            .storage
+            .set_contains(&PlayerSet::completed("gates"), 1010)
+            .unwrap());
+        // completed players are ignored: no more requests are expected by the fixture
+        gates.process_on_own_thread(&vk, &msg).unwrap();
+    }
+
+    #[test]
+    fn test_image_target() {
+        let vk = VkApi::with_fixture("challenge_image.json");
+        // The reference hash is computed the same way `cli hash` does
+        let mut hasher = ImageMatcher::<()>::new();
+        hasher.add_hasher(crate::img_match::HashConfig::default());
+        let reference = std::fs::read(format!(
+            "{}/tests/fixtures/test.jpg",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let hash = hasher.hash(&reference).unwrap()[0].hash.as_bytes().to_vec();
+        let target = format!(r#"targets = [{{ name = "steven", hash = {:?} }}]"#, hash);
+        let salmon = behavior(
+            "salmon",
+            &format!(
+                r#"
+                [[stages]]
+                {}
+                completion_text = "first"
+                fail_text = "not it"
+                [[stages]]
+                {}
+                completion_text = "second"
+                [[stages]]
+                {}
+                completion_text = "done"
+                "#,
+                target, target, target
+            ),
+        );
+        // "not it"
+        let msg = message("", vec![photo("gradient.jpg")]);
+        salmon.process_on_own_thread(&vk, &msg).unwrap();
+        assert_eq!(salmon.storage.stage("salmon", 1010).unwrap(), 0);
+        // "first"
+        let msg = message("", vec![photo("test.jpg")]);
+        salmon.process_on_own_thread(&vk, &msg).unwrap();
+        assert_eq!(salmon.storage.stage("salmon", 1010).unwrap(), 1);
+        // "second", the photo is in a forwarded message
+        let mut msg = message("look", vec![]);
+        msg.forwarded = vec![message("", vec![photo("test.jpg")])];
+        salmon.process_on_own_thread(&vk, &msg).unwrap();
+        assert_eq!(salmon.storage.stage("salmon", 1010).unwrap(), 2);
+        // "done", the photo is in the message replied to
+        let mut msg = message("this one", vec![]);
+        msg.reply_to = Some(Box::new(message("", vec![photo("test.jpg")])));
+        salmon.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(salmon
+            .storage
+            .set_contains(&PlayerSet::completed("salmon"), 1010)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_location() {
+        let vk = VkApi::with_fixture("challenge_location.json");
+        let checkpoint = behavior(
+            "checkpoint",
+            r#"
+                [[stages]]
+                locations = [{ name = "square", latitude = 59.9398, longitude = 30.3146, radius = 50 }]
+                completion_text = "success"
+                fail_text = "fail"
+                "#,
+        );
+        let completed = || {
+            checkpoint
+                .storage
+                .set_contains(&PlayerSet::completed("checkpoint"), 1010)
+                .unwrap()
+        };
+        let msg = message("", vec![geo(59.9343, 30.3061)]);
+        checkpoint.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(!completed());
+
+        let msg = message("", vec![geo(59.9399, 30.3148)]);
+        checkpoint.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(completed());
+        checkpoint.process_on_own_thread(&vk, &msg).unwrap();
+    }
+
+    #[test]
+    fn test_ordered_stages() {
+        let vk = VkApi::with_fixture("challenge_ordered.json");
+        let riddles = behavior(
+            "riddles",
+            r#"
+                [[stages]]
+                answers = [
+                    { name = "a", answer = "first" },
+                    { name = "b", answer = "second" },
+                    { name = "c", answer = "third" },
+                ]
+                required = 2
+                ordered = true
+                completion_text = "stage 1 completed"
+                wrong_stage_text = "not yet"
+
+                [[stages]]
+                answers = [{ name = "d", answer = "fourth" }]
+                completion_text = "stage 2 completed"
+                "#,
+        );
+        let send = |text| {
+            riddles
+                .process_on_own_thread(&vk, &message(text, vec![]))
+                .unwrap()
+        };
+        // out of order: "0/2"
+        send("second");
+        // "1/2"
+        send("first");
+        // "stage 1 completed"
+        send("second");
+        assert_eq!(riddles.storage.stage("riddles", 1010).unwrap(), 1);
+        // "0/1", then "stage 2 completed"
+        send("third");
+        send("fourth");
+        assert!(riddles
+            .storage
+            .set_contains(&PlayerSet::completed("riddles"), 1010)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_wrong_stage() {
+        let vk = VkApi::with_fixture("challenge_wrong_stage.json");
+        let riddles = behavior(
+            "riddles",
+            r#"
+                [[stages]]
+                answers = [{ name = "a", answer = "first" }]
+                completion_text = "stage 1 completed"
+                wrong_stage_text = "not yet"
+
+                [[stages]]
+                answers = [
+                    { name = "b", answer = "second" },
+                    { name = "c", answer = "first" },
+                ]
+                completion_text = "stage 2 completed"
+                "#,
+        );
+        // "not yet"
+        riddles
+            .process_on_own_thread(&vk, &message("second", vec![]))
+            .unwrap();
+        assert_eq!(riddles.storage.stage("riddles", 1010).unwrap(), 0);
+        // "stage 1 completed", even though the answer is also a check of stage 2
+        riddles
+            .process_on_own_thread(&vk, &message("first", vec![]))
+            .unwrap();
+        assert_eq!(riddles.storage.stage("riddles", 1010).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_hint_and_link_buttons() {
+        let vk = VkApi::with_fixture("challenge_buttons.json");
+        let riddle = behavior(
+            "riddle",
+            r#"
+                [[stages]]
+                answers = [{ name = "a", answer = "salmon" }]
+                completion_text = "correct"
+                completion_link = { label = "Next", url = "https://vk.com/downthewater" }
+                hint = "it swims"
+                "#,
+        );
+        riddle
+            .process_on_own_thread(&vk, &message("trout", vec![]))
+            .unwrap();
+        let hint_pressed = VkMessageEvent {
+            event_id: "e1".into(),
+            user_id: 1010,
+            peer_id: 1010,
+            payload: Some(serde_json::json!({ "command": "hint" })),
+        };
+        riddle
+            .process_event_on_own_thread(&vk, &hint_pressed)
+            .unwrap();
+        riddle
+            .process_on_own_thread(&vk, &message("salmon", vec![]))
+            .unwrap();
+        // No hints once the challenge is completed
+        riddle
+            .process_event_on_own_thread(&vk, &hint_pressed)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_team_in_group_chat() {
+        let vk = VkApi::with_fixture("challenge_team.json");
+        let riddles = behavior(
+            "riddles",
+            r#"
+                [[stages]]
+                answers = [{ name = "a", answer = "salmon" }, { name = "b", answer = "trout" }]
+                completion_text = "the team wins"
+                "#,
+        );
+        let mut first = message("salmon", vec![]);
+        first.peer_id = 2_000_000_001;
+        riddles.process_on_own_thread(&vk, &first).unwrap();
+        let mut second = message("trout", vec![]);
+        second.from_id = 1020;
+        second.peer_id = 2_000_000_001;
+        riddles.process_on_own_thread(&vk, &second).unwrap();
+        assert!(riddles
+            .storage
+            .set_contains(&PlayerSet::completed("riddles"), 2_000_000_001)
+            .unwrap());
+        assert!(!riddles
+            .storage
+            .set_contains(&PlayerSet::completed("riddles"), 1010)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_templates() {
+        let vk = VkApi::with_fixture("challenge_templates.json");
+        let spells = behavior(
+            "spells",
+            r#"
+                [[stages]]
+                answers = [
+                    { name = "a", answer = "a" },
+                    { name = "b", answer = "b" },
+                    { name = "c", answer = "c" },
+                ]
+                completion_text = "Этап {stage} из {stages} пройден"
+                progress_text = "{name}, осталось {remaining|заклинание|заклинания|заклинаний}"
+                "#,
+        );
+        for text in &["a", "b", "c"] {
+            spells
+                .process_on_own_thread(&vk, &message(text, vec![]))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_admin_state_per_challenge() {
+        let vk = VkApi::with_fixture("challenge_admin.json");
+        let stages = r#"
+            [[stages]]
+            answers = [{ name = "code", answer = "1" }]
+            completion_text = "next"
+            fail_text = "fail"
+            [[stages]]
+            answers = [{ name = "code", answer = "2" }]
+            completion_text = "done"
+            fail_text = "fail"
+            "#;
+        let mut gates = behavior("gates", stages);
+        gates.admin_ids = vec![1010];
+        let mut stone = behavior("stone", stages);
+        stone.admin_ids = vec![1010];
+
+        // the user is picked in one challenge...
+        gates
+            .process_on_own_thread(&vk, &message("vk.com/michiganjfrog", vec![]))
+            .unwrap();
+        // ...so the other one still asks for a link
+        stone
+            .process_on_own_thread(&vk, &message("этап 2", vec![]))
+            .unwrap();
+        gates
+            .process_on_own_thread(&vk, &message("этап 2", vec![]))
+            .unwrap();
+        assert_eq!(gates.storage.stage("gates", 1).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_admin_moves_team() {
+        let vk = VkApi::with_fixture("challenge_admin_team.json");
+        let mut gates = behavior(
+            "gates",
+            r#"
+            [[stages]]
+            answers = [{ name = "code", answer = "1" }]
+            completion_text = "next"
+            [[stages]]
+            answers = [{ name = "code", answer = "2" }]
+            completion_text = "done"
+            "#,
+        );
+        gates.admin_ids = vec![1010];
+        gates
+            .process_on_own_thread(&vk, &message("2000000001", vec![]))
+            .unwrap();
+        gates
+            .process_on_own_thread(&vk, &message("этап 2", vec![]))
+            .unwrap();
+        assert_eq!(gates.storage.stage("gates", 2_000_000_001).unwrap(), 1);
+        // in the team's chat, the admin plays along
+        let mut msg = message("2", vec![]);
+        msg.peer_id = 2_000_000_001;
+        gates.process_on_own_thread(&vk, &msg).unwrap();
+        assert!(gates
+            .storage
+            .set_contains(&PlayerSet::completed("gates"), 2_000_000_001)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_admin_act_kept_on_error() {
+        let vk = VkApi::with_fixture("challenge_admin_send_failed.json");
+        let mut gates = behavior(
+            "gates",
+            r#"
+            [[stages]]
+            answers = [{ name = "code", answer = "1" }]
+            completion_text = "next"
+            [[stages]]
+            answers = [{ name = "code", answer = "2" }]
+            completion_text = "done"
+            "#,
+        );
+        gates.admin_ids = vec![1010];
+        gates
+            .process_on_own_thread(&vk, &message("2000000001", vec![]))
+            .unwrap();
+        assert!(gates
+            .process_on_own_thread(&vk, &message("этап 2", vec![]))
+            .is_err());
+        // the admin is still editing the team and can resend the command
+        gates
+            .process_on_own_thread(&vk, &message("этап 2", vec![]))
+            .unwrap();
+        assert_eq!(gates.storage.stage("gates", 2_000_000_001).unwrap(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::behavior::{ChallengeBehavior, ThreadResult};
+use crate::storage::PlayerSet;
+use crate::vkapi::{
+    Client, VkApi, VkButtonColor, VkKeyboard, VkMessage, VkMessagesApi, VkUser, VkUsersApi,
+    GROUP_CHAT_PEER_OFFSET,
+};
+use crate::BotResult;
+
+/// What an admin is in the middle of doing, tracked separately for every challenge
+#[derive(Clone)]
+pub enum AdminAct {
+    None,
+    EditPlayer(Player),
+}
+
+/// Progress is stored per conversation, so a team is edited through its group chat
+#[derive(Clone)]
+pub enum Player {
+    User(VkUser),
+    /// The peer id of the chat
+    Chat(i64),
+}
+
+impl Player {
+    fn id(&self) -> i64 {
+        match self {
+            Player::User(user) => user.id,
+            Player::Chat(peer_id) => *peer_id,
+        }
+    }
+}
+
+impl std::fmt::Display for Player {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Player::User(user) => write!(f, "{}", user),
+            Player::Chat(peer_id) => write!(f, "Беседа {}", peer_id),
+        }
+    }
+}
+
+pub trait ChallengeAdmin<C: Client> {
+    fn reply_admin(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult;
+}
+
+const USAGE_START: &str = "Отправь ссылку на страницу пользователя в формате vk.com/name \
+                           или peer_id беседы команды (например, 2000000001)";
+fn usage_no_user(name: &str) -> String {
+    format!("Пользователь {} не найден. {}", name, USAGE_START)
+}
+fn usage_player(player: &Player) -> String {
+    format!(
+        r#"{}
+Выбери этап или напиши "этап n", чтобы перевести игрока на другой этап (например, "этап 2").
+Нажми "отмена" или напиши "отмена", чтобы выбрать другого игрока.
+"#,
+        player
+    )
+}
+
+const STAGE_BUTTONS_PER_ROW: usize = 4;
+
+/// Buttons send the same commands an admin would type
+fn stage_keyboard(stages: usize) -> VkKeyboard {
+    let mut keyboard = VkKeyboard::one_time();
+    for stage in 1..=stages {
+        let label = format!("Этап {}", stage);
+        let command = format!("этап {}", stage);
+        keyboard = keyboard.text(&label, &command, VkButtonColor::Primary);
+        if stage % STAGE_BUTTONS_PER_ROW == 0 {
+            keyboard = keyboard.row();
+        }
+    }
+    keyboard
+        .row()
+        .text("Отмена", "отмена", VkButtonColor::Negative)
+}
+
+impl<C: Client> ChallengeAdmin<C> for ChallengeBehavior {
+    fn reply_admin(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult {
+        // The lock is not held across VK and storage calls; if any of them fails,
+        // the admin stays where they were and can simply resend the message
+        let act = self.admin_acts.lock()?.get(&msg.from_id).cloned();
+        let act = act.unwrap_or(AdminAct::None);
+        let (next_act, result) = match next_admin_act(self, vk, msg, act.clone()) {
+            Ok(next_act) => (next_act, Ok(())),
+            Err(e) => (act, Err(e)),
+        };
+        self.admin_acts.lock()?.insert(msg.from_id, next_act);
+        result
+    }
+}
+
+fn next_admin_act<C: Client>(
+    behavior: &ChallengeBehavior,
+    vk: &VkApi<C>,
+    msg: &VkMessage,
+    act: AdminAct,
+) -> BotResult<AdminAct> {
+    let keyboard = stage_keyboard(behavior.challenge.stages.len());
+    let next_act = match act {
+        AdminAct::None => {
+            let text = msg.text.trim();
+            let chat = text
+                .parse::<i64>()
+                .ok()
+                .filter(|&peer_id| peer_id >= GROUP_CHAT_PEER_OFFSET);
+            if let Some(peer_id) = chat {
+                let player = Player::Chat(peer_id);
+                vk.reply(msg, &usage_player(&player), None, Some(&keyboard), None)?;
+                AdminAct::EditPlayer(player)
+            } else if text.starts_with("vk.com/") {
+                let name = text[7..].trim_end_matches('/');
+                if let Some(user) = vk.get_user(name)? {
+                    let player = Player::User(user);
+                    vk.reply(msg, &usage_player(&player), None, Some(&keyboard), None)?;
+                    AdminAct::EditPlayer(player)
+                } else {
+                    vk.reply(msg, &usage_no_user(name), None, None, None)?;
+                    AdminAct::None
+                }
+            } else {
+                vk.reply(msg, USAGE_START, None, None, None)?;
+                AdminAct::None
+            }
+        }
+        AdminAct::EditPlayer(player) => {
+            let command = match msg.command() {
+                Some(command) => command.to_owned(),
+                None => msg.text.trim().to_lowercase(),
+            };
+            match command.as_str() {
+                "отмена" => {
+                    vk.reply(msg, USAGE_START, None, Some(&VkKeyboard::empty()), None)?;
+                    AdminAct::None
+                }
+                _ if command.starts_with("этап ") => {
+                    match u64::from_str_radix(&command.replace("этап ", ""), 10) {
+                        Ok(st) if st > 0 && st as usize <= behavior.challenge.stages.len() => {
+                            let stage = st as usize - 1;
+                            behavior
+                                .storage
+                                .set_stage(&behavior.id, player.id(), stage)?;
+                            let completed_set = PlayerSet::completed(&behavior.id);
+                            behavior.storage.set_remove(&completed_set, player.id())?;
+                            let reply = format!("{} теперь на этапе {}", player, st);
+                            vk.reply(msg, &reply, None, Some(&VkKeyboard::empty()), None)?;
+                            AdminAct::None
+                        }
+                        _ => {
+                            let reply = "Пришли номер этапа как число, например, \"этап 2\"";
+                            vk.reply(msg, reply, None, Some(&keyboard), None)?;
+                            AdminAct::EditPlayer(player)
+                        }
+                    }
+                }
+                _ => {
+                    vk.reply(msg, &usage_player(&player), None, Some(&keyboard), None)?;
+                    AdminAct::EditPlayer(player)
+                }
+            }
+        }
+    };
+    Ok(next_act)
+}
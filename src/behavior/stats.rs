@@ -1,15 +1,21 @@
 use crate::behavior::{Behavior, ThreadResult};
-use crate::storage::Storage;
+use crate::quest::Quest;
+use crate::storage::{PlayerSet, Storage};
 use crate::vkapi::{Client, VkApi, VkMessage, VkMessagesApi};
 
 pub struct StatsBehavior {
     storage: Storage,
     admin_ids: Vec<i64>,
+    quest: Quest,
 }
 
 impl StatsBehavior {
-    pub fn new(storage: Storage, admin_ids: Vec<i64>) -> Self {
-        Self { storage, admin_ids }
+    pub fn new(storage: Storage, admin_ids: Vec<i64>, quest: Quest) -> Self {
+        Self {
+            storage,
+            admin_ids,
+            quest,
+        }
     }
 }
 
@@ -20,40 +26,83 @@ impl std::fmt::Display for StatsBehavior {
 }
 
 impl<C: Client> Behavior<C> for StatsBehavior {
-    fn process_on_own_thread<'s>(&'s self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult<'s> {
+    fn process_on_own_thread(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult {
         if !self.admin_ids.contains(&msg.from_id) {
             return Ok(());
         }
 
         use std::fmt::Write;
-        let mut s = String::new();
-
-        s.push_str("Камень в лесу:\n");
-
-        use crate::behavior::stone::{storage_letter_bucket, STAGE_HASHES};
-        for (stage, letters_hashes) in STAGE_HASHES.iter().enumerate() {
-            let letters = letters_hashes
-                .into_iter()
-                .map(|&(letter, _)| letter)
-                .collect::<Vec<_>>();
-            let letter_completions = self
-                .storage
-                .sets_len(letters.iter().map(|&l| storage_letter_bucket(l)))?;
-
-            write!(&mut s, "Этап {}:\n", stage + 1).unwrap();
-            for (letter, completed_by) in letters.iter().zip(letter_completions) {
-                write!(&mut s, "- {}: {}\n", letter, completed_by).unwrap();
+        let mut sections = Vec::new();
+
+        for (id, challenge) in self.quest.challenges.iter() {
+            let mut s = String::new();
+            let completions = self.storage.sets_len(&[PlayerSet::completed(id)])?[0];
+            write!(&mut s, "{}: {}", challenge.title(id), completions).unwrap();
+
+            // Single-check challenges are fully described by the number of completions
+            if challenge.stages.len() == 1 && challenge.stages[0].check_names().len() == 1 {
+                sections.push(s);
+                continue;
+            }
+            for (stage, stage_def) in challenge.stages.iter().enumerate() {
+                let checks = stage_def.check_names();
+                let buckets = checks
+                    .iter()
+                    .map(|check| PlayerSet::check_bucket(id, check))
+                    .collect::<Vec<_>>();
+                let check_completions = self.storage.sets_len(&buckets)?;
+
+                write!(&mut s, "\nЭтап {}:", stage + 1).unwrap();
+                for (check, completed_by) in checks.iter().zip(check_completions) {
+                    write!(&mut s, "\n- {}: {}", check, completed_by).unwrap();
+                }
             }
+            sections.push(s);
         }
 
-        use crate::behavior::chest::STORAGE_COMPL_SET as CHEST_KEY;
-        let chest_completions = self.storage.sets_len([CHEST_KEY].iter())?[0];
-        write!(&mut s, "\nСундук: {}\n", chest_completions).unwrap();
+        vk.reply(msg, &sections.join("\n\n"), None, None, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let vk = VkApi::with_fixture("stats.json");
+        let path = format!("{}/tests/fixtures/quest.toml", env!("CARGO_MANIFEST_DIR"));
+        let quest = Quest::load(std::path::Path::new(&path)).unwrap();
 
-        use crate::behavior::gates::STORAGE_COMPL_SET as GATES_KEY;
-        let gates_completions = self.storage.sets_len([GATES_KEY].iter())?[0];
-        write!(&mut s, "\nВорота: {}", gates_completions).unwrap();
+        let storage = Storage::in_memory();
+        storage
+            .set_add(&PlayerSet::check_bucket("stone", "1-а"), 10)
+            .unwrap();
+        storage
+            .set_add(&PlayerSet::check_bucket("stone", "1-а"), 20)
+            .unwrap();
+        storage
+            .set_add(&PlayerSet::check_bucket("stone", "1-б"), 10)
+            .unwrap();
+        storage.set_add(&PlayerSet::completed("gates"), 10).unwrap();
 
-        vk.send(msg.from_id, &s, None)
+        let stats = StatsBehavior::new(storage, vec![1], quest);
+        let mut msg = VkMessage {
+            text: String::new(),
+            from_id: 2,
+            peer_id: 2,
+            id: 1,
+            conversation_message_id: 1,
+            date: 0,
+            attachments: vec![],
+            forwarded: vec![],
+            reply_to: None,
+            payload: None,
+        };
+        // only admins get a reply
+        stats.process_on_own_thread(&vk, &msg).unwrap();
+        msg.from_id = 1;
+        msg.peer_id = 1;
+        stats.process_on_own_thread(&vk, &msg).unwrap();
     }
 }
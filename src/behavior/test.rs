@@ -1,15 +1,33 @@
 use crate::behavior::{Behavior, ThreadResult};
-use crate::img_match::ImageMatcher;
+use crate::img_match::{HashConfig, ImageMatcher, ImageVariant};
+use crate::quest::Quest;
 use crate::vkapi::{Client, VkApi, VkMessage, VkMessagesApi, VkPhotosApi};
+use crate::BotResult;
 
 pub struct TestBehavior {
-    matcher: ImageMatcher,
+    matcher: ImageMatcher<String>,
+    /// The largest of the widths configured for the challenges
+    image_width: u64,
 }
 
 impl TestBehavior {
-    pub fn new() -> Self {
-        let matcher = ImageMatcher::new();
-        Self { matcher }
+    pub fn new(quest: Quest) -> BotResult<Self> {
+        let mut matcher = ImageMatcher::new();
+        // Hashes for new targets are computed with the default config
+        matcher.add_hasher(HashConfig::default());
+        for target in quest.image_targets() {
+            matcher.add_target(target, target.name.clone())?;
+        }
+        let image_width = quest
+            .challenges
+            .values()
+            .map(|c| c.image_width)
+            .max()
+            .unwrap_or(0);
+        Ok(Self {
+            matcher,
+            image_width,
+        })
     }
 }
 
@@ -20,29 +38,37 @@ impl std::fmt::Display for TestBehavior {
 }
 
 impl<C: Client> Behavior<C> for TestBehavior {
-    fn process_on_own_thread<'s>(&'s self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult<'s> {
-        let attachments = msg.all_attachments();
+    fn process_on_own_thread(&self, vk: &VkApi<C>, msg: &VkMessage) -> ThreadResult {
+        let attachments = msg.all_photos();
         if attachments.is_empty() {
-            vk.send(msg.from_id, "No images received", None)?;
+            vk.reply(msg, "No images received", None, None, None)?;
         }
         for att in attachments {
-            let image = vk.download_photo(att)?;
-            let hash = self.matcher.hash(&image)?;
+            let image = vk.download_photo(att, self.image_width)?;
+            let hashes = self.matcher.hash(&image)?;
 
             use std::fmt::Write;
-            let mut reply = format!("Hash: {:?}", hash.as_bytes());
-            for letters in crate::behavior::stone::STAGE_HASHES.iter() {
-                for (letter, letter_hash) in letters.iter() {
-                    write!(
-                        &mut reply,
-                        ", -> {}: {}",
-                        letter,
-                        hamming::distance(letter_hash, hash.as_bytes())
-                    )
-                    .unwrap();
-                }
+            let mut reply = String::new();
+            for h in hashes
+                .iter()
+                .filter(|h| h.variant == ImageVariant::Original)
+            {
+                writeln!(&mut reply, "{}: {:?}", h.config, h.hash.as_bytes()).unwrap();
+            }
+            reply.push_str("Matches:");
+            let found = self.matcher.find(&hashes);
+            if found.is_empty() {
+                reply.push_str(" none");
+            }
+            for m in found {
+                write!(
+                    &mut reply,
+                    " {} ({:.3}, {}/{} hashes, {:?})",
+                    m.value, m.score, m.agreed, m.total, m.variant
+                )
+                .unwrap();
             }
-            vk.send(msg.from_id, &reply, None)?;
+            vk.reply(msg, &reply, None, None, None)?;
         }
         Ok(())
     }
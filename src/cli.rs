@@ -0,0 +1,185 @@
+use crate::img_match::{HashAlgorithm, HashConfig, ImageMatcher, ImageVariant, DEFAULT_HASH_SIZE};
+use crate::quest::Quest;
+use crate::BotResult;
+use std::path::{Path, PathBuf};
+
+/// `salmonbot hash [--alg ALG] [--size WxH] FILES...`: prints a quest target for each file
+pub fn print_hashes(args: &[String]) -> BotResult<()> {
+    let mut alg = HashAlgorithm::default();
+    let mut size = DEFAULT_HASH_SIZE;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--alg" => alg = parse_alg(args.next().map(|a| a.as_str()).unwrap_or_default())?,
+            "--size" => size = parse_size(args.next().map(|a| a.as_str()).unwrap_or_default())?,
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    if files.is_empty() {
+        return Err("No image files specified".into());
+    }
+
+    let config = HashConfig {
+        alg,
+        width: size[0],
+        height: size[1],
+        dct: alg != HashAlgorithm::Blockhash,
+    };
+    let mut matcher = ImageMatcher::<()>::new();
+    matcher.add_hasher(config);
+    for path in files {
+        let image = open_image(&path)?;
+        let hashes = matcher.hash_image(&image);
+        println!(
+            "{}",
+            format_target(&path, config, hashes[0].hash.as_bytes())
+        );
+    }
+    Ok(())
+}
+
+/// `salmonbot verify QUEST DIR`: reports the targets matched by each image in the directory
+pub fn verify(quest_path: &Path, dir: &Path) -> BotResult<()> {
+    let quest = Quest::load(quest_path)?;
+    let mut matcher = ImageMatcher::new();
+    for target in quest.image_targets() {
+        matcher.add_target(target, target.name.clone())?;
+    }
+
+    let mut files = std::fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut matched = 0;
+    for path in files.iter() {
+        let image = match open_image(path) {
+            Ok(image) => image,
+            Err(e) => {
+                println!("{}: skipped ({})", path.display(), e);
+                continue;
+            }
+        };
+        let found = matcher.find(&matcher.hash_image(&image));
+        if found.is_empty() {
+            println!("{}: no match", path.display());
+            continue;
+        }
+        matched += 1;
+        let targets = found
+            .iter()
+            .map(|m| {
+                let mut desc = format!(
+                    "{} (distance {}, score {:.3}, {}/{} hashes",
+                    m.value, m.distance, m.score, m.agreed, m.total
+                );
+                if m.variant != ImageVariant::Original {
+                    desc.push_str(&format!(", {:?}", m.variant));
+                }
+                desc.push(')');
+                desc
+            })
+            .collect::<Vec<_>>();
+        println!("{}: {}", path.display(), targets.join(", "));
+    }
+    println!("{} of {} images matched a target", matched, files.len());
+    Ok(())
+}
+
+fn open_image(path: &Path) -> BotResult<image::DynamicImage> {
+    image::open(path).map_err(|e| format!("Cannot open {}: {}", path.display(), e).into())
+}
+
+fn parse_alg(alg: &str) -> BotResult<HashAlgorithm> {
+    match alg {
+        "mean" => Ok(HashAlgorithm::Mean),
+        "gradient" => Ok(HashAlgorithm::Gradient),
+        "double_gradient" => Ok(HashAlgorithm::DoubleGradient),
+        "blockhash" => Ok(HashAlgorithm::Blockhash),
+        _ => Err(format!(
+            "Unknown hash algorithm \"{}\", expected mean, gradient, double_gradient or blockhash",
+            alg
+        )
+        .into()),
+    }
+}
+
+fn parse_size(size: &str) -> BotResult<[u32; 2]> {
+    let mut dims = size.splitn(2, 'x').map(|d| d.parse::<u32>());
+    match (dims.next(), dims.next()) {
+        (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => Ok([width, height]),
+        _ => Err(format!("Invalid hash size \"{}\", expected WIDTHxHEIGHT", size).into()),
+    }
+}
+
+/// A target definition ready to be pasted into the quest file
+fn format_target(path: &Path, config: HashConfig, hash: &[u8]) -> String {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let hash = hash
+        .iter()
+        .map(|b| b.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut target = format!("{{ name = {:?}, hash = [{}]", name, hash);
+    if config.alg != HashAlgorithm::default() {
+        let alg = match config.alg {
+            HashAlgorithm::Mean => "mean",
+            HashAlgorithm::Gradient => "gradient",
+            HashAlgorithm::DoubleGradient => "double_gradient",
+            HashAlgorithm::Blockhash => "blockhash",
+        };
+        target.push_str(&format!(", alg = \"{}\"", alg));
+    }
+    if [config.width, config.height] != DEFAULT_HASH_SIZE {
+        target.push_str(&format!(
+            ", hash_size = [{}, {}]",
+            config.width, config.height
+        ));
+    }
+    target.push_str(" }");
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_target() {
+        let config = HashConfig {
+            alg: HashAlgorithm::Blockhash,
+            width: 12,
+            height: 12,
+            dct: false,
+        };
+        assert_eq!(
+            format_target(Path::new("photos/1-а.jpg"), config, &[1, 2, 255]),
+            r#"{ name = "1-а", hash = [1, 2, 255], alg = "blockhash", hash_size = [12, 12] }"#
+        );
+        assert_eq!(
+            format_target(Path::new("wrench.jpg"), HashConfig::default(), &[7]),
+            r#"{ name = "wrench", hash = [7] }"#
+        );
+        assert_eq!(parse_size("12x8").unwrap(), [12, 8]);
+        assert!(parse_size("12").is_err());
+        assert!(parse_alg("phash").is_err());
+    }
+
+    #[test]
+    fn test_formatted_target_parses() {
+        let formatted = format_target(Path::new("a.jpg"), HashConfig::default(), &[0; 18]);
+        let quest: Quest = toml::from_str(&format!(
+            "[[challenges.chest.stages]]\ntargets = [{}]\ncompletion_text = \"\"",
+            formatted
+        ))
+        .unwrap();
+        let target = &quest.challenges["chest"].stages[0].targets[0];
+        assert_eq!(target.hashes()[0].hash_config(), HashConfig::default());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::BotResult;
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Configuration for hosting several communities in a single process
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    /// Quest definition path, relative to the config file. Defaults to `SALMON_QUEST`
+    pub quest: Option<PathBuf>,
+    /// Storage URL shared by all communities. Defaults to `SALMON_STORAGE`
+    pub storage: Option<String>,
+    /// Defaults to `SALMON_ADMIN_IDS`
+    pub admin_ids: Option<Vec<i64>>,
+    /// Address of the Callback API server, required if any community uses it
+    pub callback_listen: Option<String>,
+    #[serde(rename = "community")]
+    pub communities: Vec<CommunityConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommunityConfig {
+    pub behavior: String,
+    pub token: String,
+    /// Receive events through the Callback API instead of long polling
+    pub callback: Option<CallbackConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CallbackConfig {
+    /// The string the server should return, shown in the Callback API settings
+    pub confirmation: String,
+    pub secret: Option<String>,
+}
+
+impl BotConfig {
+    pub fn load(path: &Path) -> BotResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+        let mut config: BotConfig = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+        if config.communities.is_empty() {
+            return Err(format!("No communities listed in {}", path.display()).into());
+        }
+        let uses_callback = config.communities.iter().any(|c| c.callback.is_some());
+        if uses_callback && config.callback_listen.is_none() {
+            return Err(format!(
+                "{}: callback_listen is required for communities using the Callback API",
+                path.display()
+            )
+            .into());
+        }
+        if let Some(quest) = config.quest.take() {
+            let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+            config.quest = Some(base_dir.join(quest));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_config() {
+        let path = format!("{}/salmonbot.example.toml", env!("CARGO_MANIFEST_DIR"));
+        let config = BotConfig::load(Path::new(&path)).unwrap();
+        assert_eq!(
+            config.quest,
+            Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("quest.toml"))
+        );
+        assert_eq!(config.admin_ids, Some(vec![1]));
+        let behaviors = config
+            .communities
+            .iter()
+            .map(|c| c.behavior.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(behaviors, vec!["chest", "gates", "stone"]);
+        assert_eq!(config.callback_listen.as_deref(), Some("127.0.0.1:8080"));
+        let callback = config.communities[2].callback.as_ref().unwrap();
+        assert_eq!(callback.confirmation, "1a2b3c4d");
+        assert_eq!(callback.secret.as_deref(), Some("stone community secret"));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::vkapi::VkApiError;
+
+pub type BotResult<T> = Result<T, BotError>;
+
+#[derive(Debug)]
+pub enum BotError {
+    /// Connection failures, timeouts and garbled responses, usually resolved by trying again
+    Network(String),
+    /// An error object returned by the VK API
+    VkApi(VkApiError),
+    /// The storage backend failed; `fatal` if it is unusable (e.g. the connection is lost)
+    Storage { message: String, fatal: bool },
+    /// The player sent something the bot cannot make sense of, e.g. a broken image
+    BadInput(String),
+    /// Everything else, such as an unexpected response structure
+    Other(String),
+}
+
+/// What to do with a message whose processing has failed
+#[derive(Debug, PartialEq)]
+pub enum ErrorPolicy {
+    /// Process the message again after a short delay
+    Retry,
+    /// Let the player know that something went wrong
+    Apologize,
+    /// Log the error and move on to the next message
+    Skip,
+    /// Stop the bot, further messages cannot be processed either
+    Shutdown,
+}
+
+impl BotError {
+    pub fn storage<E: std::fmt::Display>(message: String, error: E, fatal: bool) -> Self {
+        BotError::Storage {
+            message: format!("{}: {}", message, error),
+            fatal,
+        }
+    }
+
+    pub fn policy(&self) -> ErrorPolicy {
+        match self {
+            BotError::Network(_) => ErrorPolicy::Retry,
+            BotError::VkApi(e) if e.is_auth() => ErrorPolicy::Shutdown,
+            // Transient API errors have already been retried by `VkApi::call_api`
+            BotError::VkApi(_) => ErrorPolicy::Skip,
+            BotError::Storage { fatal: true, .. } => ErrorPolicy::Shutdown,
+            BotError::Storage { fatal: false, .. } => ErrorPolicy::Apologize,
+            BotError::BadInput(_) => ErrorPolicy::Apologize,
+            BotError::Other(_) => ErrorPolicy::Skip,
+        }
+    }
+}
+
+impl std::fmt::Display for BotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BotError::Network(message) => write!(f, "Network error: {}", message),
+            BotError::VkApi(e) if e.is_auth() => write!(
+                f,
+                "{}\nThe community token is invalid or has been revoked, please issue a new one",
+                e
+            ),
+            BotError::VkApi(e) => write!(f, "{}", e),
+            BotError::Storage { message, .. } => write!(f, "Storage error: {}", message),
+            BotError::BadInput(message) => write!(f, "Bad input: {}", message),
+            BotError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for BotError {}
+
+impl From<String> for BotError {
+    fn from(message: String) -> Self {
+        BotError::Other(message)
+    }
+}
+
+impl From<&str> for BotError {
+    fn from(message: &str) -> Self {
+        BotError::Other(message.to_owned())
+    }
+}
+
+impl From<std::io::Error> for BotError {
+    fn from(error: std::io::Error) -> Self {
+        BotError::Network(error.to_string())
+    }
+}
+
+/// Malformed JSON will not get any better if the same request is repeated
+impl From<serde_json::Error> for BotError {
+    fn from(error: serde_json::Error) -> Self {
+        BotError::Other(format!("Unable to deserialize JSON: {}", error))
+    }
+}
+
+impl From<image::ImageError> for BotError {
+    fn from(error: image::ImageError) -> Self {
+        BotError::BadInput(format!("Unable to decode image: {}", error))
+    }
+}
+
+impl From<std::time::SystemTimeError> for BotError {
+    fn from(error: std::time::SystemTimeError) -> Self {
+        BotError::Other(error.to_string())
+    }
+}
+
+/// A worker panicked while holding the lock. The panic only takes down its own job,
+/// so the message that ran into the poisoned lock is apologized for instead of stopping the bot.
+impl<T> From<std::sync::PoisonError<T>> for BotError {
+    fn from(error: std::sync::PoisonError<T>) -> Self {
+        BotError::Storage {
+            message: error.to_string(),
+            fatal: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let vk_error = |code| {
+            BotError::VkApi(VkApiError {
+                code,
+                message: String::new(),
+                request_params: vec![],
+            })
+        };
+        assert_eq!(vk_error(5).policy(), ErrorPolicy::Shutdown);
+        assert_eq!(vk_error(901).policy(), ErrorPolicy::Skip);
+        assert_eq!(
+            BotError::storage("Cannot add".into(), "broken pipe", true).policy(),
+            ErrorPolicy::Shutdown
+        );
+        assert_eq!(
+            BotError::BadInput(String::new()).policy(),
+            ErrorPolicy::Apologize
+        );
+        assert_eq!(BotError::from("unexpected").policy(), ErrorPolicy::Skip);
+        let json_error = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(BotError::from(json_error).policy(), ErrorPolicy::Skip);
+
+        let lock = std::sync::Arc::new(std::sync::Mutex::new(()));
+        let poisoned = lock.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        let poison_error = BotError::from(lock.lock().unwrap_err());
+        assert_eq!(poison_error.policy(), ErrorPolicy::Apologize);
+    }
+}
@@ -1,30 +1,469 @@
+use crate::quest::Target;
 use crate::BotResult;
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 
-const HAMMING_TOLERANCE: u64 = 7;
+mod bk_tree;
+use bk_tree::BkTree;
 
-pub struct ImageMatcher {
+pub const HAMMING_TOLERANCE: u64 = 7;
+pub const DEFAULT_HASH_SIZE: [u32; 2] = [15, 15];
+/// The share of the width and height kept by `ImageVariant::CenterCrop`
+const CENTER_CROP_RATIO: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Mean,
+    Gradient,
+    DoubleGradient,
+    Blockhash,
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> Self {
+        HashAlgorithm::DoubleGradient
+    }
+}
+
+/// Transformations of submitted images a target tolerates, listed in the quest file
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantKind {
+    /// The photo is rotated by 90, 180 or 270 degrees
+    Rotate,
+    /// The photo is mirrored horizontally, as front cameras tend to do
+    Mirror,
+    /// The photo is cropped tighter than the reference
+    Crop,
+}
+
+impl VariantKind {
+    fn variants(self) -> &'static [ImageVariant] {
+        match self {
+            VariantKind::Rotate => &[
+                ImageVariant::Rotate90,
+                ImageVariant::Rotate180,
+                ImageVariant::Rotate270,
+            ],
+            VariantKind::Mirror => &[ImageVariant::Mirror],
+            VariantKind::Crop => &[ImageVariant::CenterCrop],
+        }
+    }
+}
+
+/// A transformation applied to a submitted image before hashing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageVariant {
+    Original,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Mirror,
+    CenterCrop,
+}
+
+impl ImageVariant {
+    /// Returns `None` if the variant cannot be made from the image, such as a crop
+    /// of a 1x1 sticker; the original image is borrowed to avoid copying it
+    fn apply(self, image: &image::DynamicImage) -> Option<Cow<'_, image::DynamicImage>> {
+        use image::GenericImageView;
+        match self {
+            ImageVariant::Original => Some(Cow::Borrowed(image)),
+            ImageVariant::Rotate90 => Some(Cow::Owned(image.rotate90())),
+            ImageVariant::Rotate180 => Some(Cow::Owned(image.rotate180())),
+            ImageVariant::Rotate270 => Some(Cow::Owned(image.rotate270())),
+            ImageVariant::Mirror => Some(Cow::Owned(image.fliph())),
+            ImageVariant::CenterCrop => {
+                let (width, height) = image.dimensions();
+                let crop_width = (width as f64 * CENTER_CROP_RATIO) as u32;
+                let crop_height = (height as f64 * CENTER_CROP_RATIO) as u32;
+                if crop_width == 0 || crop_height == 0 {
+                    return None;
+                }
+                Some(Cow::Owned(image.crop_imm(
+                    (width - crop_width) / 2,
+                    (height - crop_height) / 2,
+                    crop_width,
+                    crop_height,
+                )))
+            }
+        }
+    }
+}
+
+/// Everything that affects the hash of an image; hashes are only comparable
+/// if they were produced with the same config
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashConfig {
+    pub alg: HashAlgorithm,
+    pub width: u32,
+    pub height: u32,
+    pub dct: bool,
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            alg: HashAlgorithm::default(),
+            width: DEFAULT_HASH_SIZE[0],
+            height: DEFAULT_HASH_SIZE[1],
+            dct: true,
+        }
+    }
+}
+
+impl std::fmt::Display for HashConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} {}x{}", self.alg, self.width, self.height)?;
+        if self.dct {
+            write!(f, " DCT")?;
+        }
+        Ok(())
+    }
+}
+
+impl HashConfig {
+    fn to_hasher(&self) -> img_hash::Hasher {
+        let alg = match self.alg {
+            HashAlgorithm::Mean => img_hash::HashAlg::Mean,
+            HashAlgorithm::Gradient => img_hash::HashAlg::Gradient,
+            HashAlgorithm::DoubleGradient => img_hash::HashAlg::DoubleGradient,
+            HashAlgorithm::Blockhash => img_hash::HashAlg::Blockhash,
+        };
+        let config = img_hash::HasherConfig::new()
+            .hash_alg(alg)
+            .hash_size(self.width, self.height);
+        if self.dct {
+            config.preproc_dct().to_hasher()
+        } else {
+            config.to_hasher()
+        }
+    }
+}
+
+/// Target hashes sharing a hash config, along with the hasher for it
+struct HasherGroup {
+    config: HashConfig,
     hasher: img_hash::Hasher,
+    hash_len: usize,
+    /// Variants of submitted images tolerated by at least one target in the group
+    variants: Vec<ImageVariant>,
+    /// Hashes are tagged with the index of their target in `ImageMatcher::targets`
+    index: BkTree<usize>,
 }
 
-impl ImageMatcher {
+struct TargetEntry<T> {
+    value: T,
+    hashes: usize,
+    consensus: usize,
+    variants: Vec<ImageVariant>,
+}
+
+/// A hash of a submitted image, or of one of its variants
+pub struct SubmissionHash {
+    pub variant: ImageVariant,
+    pub config: HashConfig,
+    pub hash: img_hash::ImageHash,
+}
+
+#[derive(Debug)]
+pub struct TargetMatch<'a, T> {
+    pub value: &'a T,
+    /// The variant of the submitted image that matched the target
+    pub variant: ImageVariant,
+    /// The number of target hashes within tolerance, out of `total`
+    pub agreed: usize,
+    /// The largest distance among the hashes within tolerance
+    pub distance: u64,
+    pub total: usize,
+    /// From 0 to 1, with 1 meaning that every hash of the target is an exact match
+    pub score: f64,
+}
+
+/// Hashes incoming images and looks them up among the registered targets.
+/// An image is hashed once for every distinct config used by the targets,
+/// plus once per config for every variant the targets opt into.
+pub struct ImageMatcher<T> {
+    groups: Vec<HasherGroup>,
+    targets: Vec<TargetEntry<T>>,
+}
+
+impl<T> ImageMatcher<T> {
     pub fn new() -> Self {
-        let hasher = img_hash::HasherConfig::new()
-            .hash_alg(img_hash::HashAlg::DoubleGradient)
-            .hash_size(15, 15)
-            .preproc_dct()
-            .to_hasher();
-        Self { hasher }
+        Self {
+            groups: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    /// Makes sure images are hashed with `config`, even if no target uses it
+    pub fn add_hasher(&mut self, config: HashConfig) {
+        self.group_mut(config);
+    }
+
+    fn group_mut(&mut self, config: HashConfig) -> &mut HasherGroup {
+        let index = match self.groups.iter().position(|g| g.config == config) {
+            Some(index) => index,
+            None => {
+                let hasher = config.to_hasher();
+                // The hash length only depends on the config, not on the image
+                let probe = image::DynamicImage::new_luma8(8, 8);
+                let hash_len = hasher.hash_image(&probe).as_bytes().len();
+                self.groups.push(HasherGroup {
+                    config,
+                    hasher,
+                    hash_len,
+                    variants: vec![ImageVariant::Original],
+                    index: BkTree::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[index]
+    }
+
+    /// Registers `target`, reporting `value` when an image matches enough of its hashes
+    pub fn add_target(&mut self, target: &Target, value: T) -> BotResult<()> {
+        let target_index = self.targets.len();
+        let mut variants = vec![ImageVariant::Original];
+        for kind in target.variants.iter() {
+            variants.extend_from_slice(kind.variants());
+        }
+        let hashes = target.hashes();
+        for hash in hashes.iter() {
+            let config = hash.hash_config();
+            let group = self.group_mut(config);
+            if hash.hash.len() != group.hash_len {
+                return Err(format!(
+                    "Target {} has a {}-byte hash, but {} hashes are {} bytes long",
+                    target.name,
+                    hash.hash.len(),
+                    config,
+                    group.hash_len
+                )
+                .into());
+            }
+            for variant in variants.iter() {
+                if !group.variants.contains(variant) {
+                    group.variants.push(*variant);
+                }
+            }
+            group
+                .index
+                .insert(hash.hash.clone(), hash.tolerance, target_index);
+        }
+        self.targets.push(TargetEntry {
+            value,
+            hashes: hashes.len(),
+            consensus: target.consensus(),
+            variants,
+        });
+        Ok(())
+    }
+
+    /// Hashes the image and its variants with every registered config
+    pub fn hash(&self, vk_image: &[u8]) -> BotResult<Vec<SubmissionHash>> {
+        // Photo previews are JPEGs, but stickers, graffiti and documents come in other formats
+        let image = image::load_from_memory(vk_image)?;
+        Ok(self.hash_image(&image))
+    }
+
+    pub fn hash_image(&self, image: &image::DynamicImage) -> Vec<SubmissionHash> {
+        let mut variants: Vec<ImageVariant> = Vec::new();
+        for group in self.groups.iter() {
+            for variant in group.variants.iter() {
+                if !variants.contains(variant) {
+                    variants.push(*variant);
+                }
+            }
+        }
+        let mut hashes = Vec::new();
+        for variant in variants {
+            let variant_image = match variant.apply(image) {
+                Some(variant_image) => variant_image,
+                None => continue,
+            };
+            for group in self.groups.iter().filter(|g| g.variants.contains(&variant)) {
+                hashes.push(SubmissionHash {
+                    variant,
+                    config: group.config,
+                    hash: group.hasher.hash_image(variant_image.as_ref()),
+                });
+            }
+        }
+        hashes
+    }
+
+    /// Returns the targets for which enough hashes are within tolerance of the submission
+    /// `hashes`, best score first. Only the best matching variant is reported for each target.
+    pub fn find(&self, hashes: &[SubmissionHash]) -> Vec<TargetMatch<'_, T>> {
+        // (hashes within tolerance, largest distance, sum of similarities) per target and variant
+        let mut agreement: HashMap<(usize, ImageVariant), (usize, u64, f64)> = HashMap::new();
+        for submission in hashes.iter() {
+            let group = match self.groups.iter().find(|g| g.config == submission.config) {
+                Some(group) => group,
+                None => continue,
+            };
+            let bits = (group.hash_len * 8) as f64;
+            for m in group.index.find(submission.hash.as_bytes()) {
+                if !self.targets[*m.value]
+                    .variants
+                    .contains(&submission.variant)
+                {
+                    continue;
+                }
+                let (agreed, distance, similarity) = agreement
+                    .entry((*m.value, submission.variant))
+                    .or_insert((0, 0, 0.0));
+                *agreed += 1;
+                *distance = (*distance).max(m.distance);
+                *similarity += 1.0 - m.distance as f64 / bits;
+            }
+        }
+        let mut best: BTreeMap<usize, TargetMatch<'_, T>> = BTreeMap::new();
+        for ((index, variant), (agreed, distance, similarity)) in agreement {
+            let target = &self.targets[index];
+            if agreed < target.consensus {
+                continue;
+            }
+            let score = similarity / target.hashes as f64;
+            if best.get(&index).map_or(true, |b| b.score < score) {
+                let found = TargetMatch {
+                    value: &target.value,
+                    variant,
+                    agreed,
+                    distance,
+                    total: target.hashes,
+                    score,
+                };
+                best.insert(index, found);
+            }
+        }
+        let mut found = best.into_iter().map(|(_, m)| m).collect::<Vec<_>>();
+        found.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consensus() {
+        let target: Target = toml::from_str(
+            r#"
+            name = "a"
+            hash = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            extra_hashes = [
+                { alg = "mean", hash_size = [12, 12], hash = [255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] },
+                { alg = "gradient", hash_size = [12, 12], hash = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0] },
+            ]
+            consensus = 2
+            "#,
+        )
+        .unwrap();
+        let mut strict = target.clone();
+        strict.consensus = None;
+
+        let mut matcher = ImageMatcher::new();
+        matcher.add_target(&target, "lenient").unwrap();
+        matcher.add_target(&strict, "strict").unwrap();
+
+        let blank = img_hash::ImageHash::from_bytes(&[0; 18]).unwrap();
+        let hashes = matcher
+            .groups
+            .iter()
+            .map(|g| SubmissionHash {
+                variant: ImageVariant::Original,
+                config: g.config,
+                hash: blank.clone(),
+            })
+            .collect::<Vec<_>>();
+        let found = matcher.find(&hashes);
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].value, "lenient");
+        assert_eq!((found[0].agreed, found[0].total), (2, 3));
+        assert_eq!(found[0].distance, 1);
+        assert!((found[0].score - (1.0 + 1.0 - 1.0 / 144.0) / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_variants() {
+        let target: Target = toml::from_str(
+            r#"
+            name = "a"
+            hash = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            variants = ["mirror"]
+            "#,
+        )
+        .unwrap();
+        let mut original_only = target.clone();
+        original_only.variants.clear();
+
+        let mut matcher = ImageMatcher::new();
+        matcher.add_target(&target, "mirror").unwrap();
+        matcher.add_target(&original_only, "original").unwrap();
+        assert_eq!(
+            matcher.groups[0].variants,
+            vec![ImageVariant::Original, ImageVariant::Mirror]
+        );
+
+        let mirrored = SubmissionHash {
+            variant: ImageVariant::Mirror,
+            config: HashConfig::default(),
+            hash: img_hash::ImageHash::from_bytes(&[0; 18]).unwrap(),
+        };
+        let found = matcher.find(&[mirrored]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(*found[0].value, "mirror");
+        assert_eq!(found[0].variant, ImageVariant::Mirror);
+    }
+
+    #[test]
+    fn test_crop_of_tiny_image() {
+        let target: Target = toml::from_str(
+            r#"
+            name = "a"
+            hash = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            variants = ["crop", "mirror"]
+            "#,
+        )
+        .unwrap();
+        let mut matcher = ImageMatcher::new();
+        matcher.add_target(&target, ()).unwrap();
+        let pixel = image::DynamicImage::new_rgb8(1, 1);
+        let variants = matcher
+            .hash_image(&pixel)
+            .iter()
+            .map(|h| h.variant)
+            .collect::<Vec<_>>();
+        assert_eq!(variants, vec![ImageVariant::Original, ImageVariant::Mirror]);
     }
 
-    pub fn hash(&self, vk_image: &[u8]) -> BotResult<img_hash::ImageHash> {
-        // Fun fact: VK image previews are JPEGs regardless of the format of the original pic
-        let image = image::load_from_memory_with_format(vk_image, image::ImageFormat::Jpeg)?;
-        let image_hash = self.hasher.hash_image(&image);
-        Ok(image_hash)
+    #[test]
+    fn test_sniff_format() {
+        let mut matcher = ImageMatcher::<()>::new();
+        matcher.add_hasher(HashConfig::default());
+        let path = format!("{}/tests/fixtures/test.jpg", env!("CARGO_MANIFEST_DIR"));
+        let hashes = matcher.hash(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(hashes.len(), 1);
+        match matcher.hash(b"definitely not an image") {
+            Err(crate::BotError::BadInput(_)) => (),
+            r => panic!("expected BadInput, got {:?}", r.map(|h| h.len())),
+        }
     }
 
-    pub fn matches(expected: &[u8], hash: &img_hash::ImageHash, tolerance_inc_hack: u64) -> bool {
-        let dist = hamming::distance(&expected, hash.as_bytes());
-        dist <= (HAMMING_TOLERANCE + tolerance_inc_hack)
+    #[test]
+    fn test_reject_hash_length() {
+        let target: Target = toml::from_str("name = 'short'\nhash = [1, 2]").unwrap();
+        let mut matcher = ImageMatcher::new();
+        let err = matcher.add_target(&target, ()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Target short has a 2-byte hash, but DoubleGradient 15x15 DCT hashes are 18 bytes long"
+        );
     }
 }
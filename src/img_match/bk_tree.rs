@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+/// A BK-tree over perceptual hashes, using the Hamming distance as the metric.
+/// Lookups only visit the subtrees that may contain hashes within the search radius,
+/// so matching against hundreds of targets does not require comparing against each one.
+pub struct BkTree<T> {
+    root: Option<Node<T>>,
+    max_tolerance: u64,
+}
+
+struct Node<T> {
+    hash: Vec<u8>,
+    /// Values sharing the same hash, each with its own tolerance
+    entries: Vec<(u64, T)>,
+    children: BTreeMap<u64, Node<T>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HashMatch<'a, T> {
+    pub value: &'a T,
+    pub distance: u64,
+}
+
+impl<T> Node<T> {
+    fn new(hash: Vec<u8>, tolerance: u64, value: T) -> Self {
+        Self {
+            hash,
+            entries: vec![(tolerance, value)],
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> BkTree<T> {
+    pub fn new() -> Self {
+        Self {
+            root: None,
+            max_tolerance: 0,
+        }
+    }
+
+    pub fn insert(&mut self, hash: Vec<u8>, tolerance: u64, value: T) {
+        self.max_tolerance = self.max_tolerance.max(tolerance);
+        let mut node = match self.root {
+            Some(ref mut root) => root,
+            None => {
+                self.root = Some(Node::new(hash, tolerance, value));
+                return;
+            }
+        };
+        loop {
+            let dist = hamming::distance(&node.hash, &hash);
+            if dist == 0 {
+                node.entries.push((tolerance, value));
+                return;
+            }
+            if !node.children.contains_key(&dist) {
+                node.children
+                    .insert(dist, Node::new(hash, tolerance, value));
+                return;
+            }
+            node = node.children.get_mut(&dist).unwrap();
+        }
+    }
+
+    /// Returns all values whose hash is within their tolerance of `hash`, closest first
+    pub fn find(&self, hash: &[u8]) -> Vec<HashMatch<'_, T>> {
+        let mut found = Vec::new();
+        let mut pending = self.root.iter().collect::<Vec<_>>();
+        while let Some(node) = pending.pop() {
+            let dist = hamming::distance(&node.hash, hash);
+            for (tolerance, value) in node.entries.iter() {
+                if dist <= *tolerance {
+                    found.push(HashMatch {
+                        value,
+                        distance: dist,
+                    });
+                }
+            }
+            // By the triangle inequality, matches can only be found in children
+            // whose distance to this node differs from `dist` by at most the search radius
+            let lo = dist.saturating_sub(self.max_tolerance);
+            let hi = dist + self.max_tolerance;
+            pending.extend(node.children.range(lo..=hi).map(|(_, child)| child));
+        }
+        found.sort_by_key(|m| m.distance);
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random_hashes(count: usize) -> Vec<Vec<u8>> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..count)
+            .map(|_| {
+                (0..18)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        state as u8
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_linear_search() {
+        let hashes = pseudo_random_hashes(300);
+        let mut tree = BkTree::new();
+        for (i, hash) in hashes.iter().enumerate() {
+            tree.insert(hash.clone(), 50 + (i as u64 % 20), i);
+        }
+        for query in pseudo_random_hashes(320).iter().skip(290) {
+            let mut expected = hashes
+                .iter()
+                .enumerate()
+                .map(|(i, h)| (i, hamming::distance(h, query)))
+                .filter(|(i, dist)| *dist <= 50 + (*i as u64 % 20))
+                .collect::<Vec<_>>();
+            expected.sort_by_key(|(i, dist)| (*dist, *i));
+            let mut found = tree
+                .find(query)
+                .into_iter()
+                .map(|m| (*m.value, m.distance))
+                .collect::<Vec<_>>();
+            found.sort_by_key(|(i, dist)| (*dist, *i));
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_ordered_by_distance() {
+        let mut tree = BkTree::new();
+        tree.insert(vec![0b0000_0000], 1, "a");
+        tree.insert(vec![0b0000_0111], 3, "b");
+        tree.insert(vec![0b0000_0001], 0, "c");
+        tree.insert(vec![0b0000_0001], 2, "d");
+        assert_eq!(
+            tree.find(&[0b0000_0001]),
+            vec![
+                HashMatch {
+                    value: &"c",
+                    distance: 0
+                },
+                HashMatch {
+                    value: &"d",
+                    distance: 0
+                },
+                HashMatch {
+                    value: &"a",
+                    distance: 1
+                },
+                HashMatch {
+                    value: &"b",
+                    distance: 2
+                },
+            ]
+        );
+        assert!(tree.find(&[0b1111_0000]).is_empty());
+    }
+}
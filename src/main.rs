@@ -1,24 +1,47 @@
 #![recursion_limit = "256"]
 
 mod vkapi;
-use vkapi::{Client, VkApi, VkLongPoll, VkMessage};
+use vkapi::{
+    Client, VkApi, VkCallbackEndpoint, VkCallbackServer, VkEvent, VkLongPoll, VkMessagesApi,
+};
+mod answer_match;
 mod behavior;
 use behavior::*;
+mod cli;
+mod config;
+mod error;
+pub use error::{BotError, BotResult, ErrorPolicy};
 mod img_match;
+mod quest;
 mod storage;
+mod template;
+mod worker_pool;
+use worker_pool::{Batch, SealedBatch, WorkerPool};
 
-use std::{env, error::Error, sync::Arc, time::Duration};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::{env, sync::Arc, time::Duration};
 
 pub const MSG_DELAY_FAIL: Duration = Duration::from_millis(4800);
 pub const MSG_DELAY_SUCCESS: Duration = Duration::from_millis(400);
+const MSG_DELAY_RETRY: Duration = Duration::from_millis(2000);
+const MSG_MAX_RETRIES: usize = 2;
+/// Upper bound for the pause between long poll requests after consecutive network failures
+const LONG_POLL_MAX_BACKOFF: Duration = Duration::from_secs(60);
+const APOLOGY_TEXT: &str = "Что-то пошло не так... Попробуй отправить сообщение еще раз чуть позже";
 
-pub type BotResult<T> = Result<T, Box<dyn Error>>;
+const STORAGE_LONG_POLL_TS: &str = "long_poll_ts";
 
-const REDIS_URL: &str = "redis://127.0.0.1/";
+const DEFAULT_STORAGE_URL: &str = "redis://127.0.0.1/";
+const DEFAULT_QUEST_PATH: &str = "quest.toml";
+const DEFAULT_WORKERS: usize = 16;
+/// Messages waiting to be processed per worker before the long poll loop is paused
+const WORKER_QUEUE_CAPACITY: usize = 64;
 
 struct Bot<C: Client> {
     behavior: Box<dyn Behavior<C>>,
     vk: VkApi<C>,
+    storage: storage::Storage,
 }
 
 impl<C: Client> std::fmt::Display for Bot<C> {
@@ -29,36 +52,175 @@ impl<C: Client> std::fmt::Display for Bot<C> {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let token = env::var("COMMUNITY_TOKEN")
-        .expect("Provide a valid API token via the COMMUNITY_TOKEN environment variable");
 
-    println!("Booting up...");
-    if let Err(err) = make_bot(args, token).and_then(run_bot) {
+    let result = match args.get(1).map(|a| a.as_str()) {
+        Some("hash") => cli::print_hashes(&args[2..]),
+        Some("verify") => match (args.get(2), args.get(3)) {
+            (Some(quest), Some(dir)) => cli::verify(Path::new(quest), Path::new(dir)),
+            _ => Err(usage(&args[0]).into()),
+        },
+        Some("run") => match args.get(2) {
+            Some(path) => {
+                println!("Booting up...");
+                config::BotConfig::load(Path::new(path)).and_then(run_communities)
+            }
+            None => Err(usage(&args[0]).into()),
+        },
+        behavior => {
+            println!("Booting up...");
+            let token = env::var("COMMUNITY_TOKEN")
+                .expect("Provide a valid API token via the COMMUNITY_TOKEN environment variable");
+            let behavior = behavior.unwrap_or_default();
+            make_single_bot(&args[0], behavior, token)
+                .and_then(|bot| run_bot(bot, Arc::new(make_worker_pool())))
+        }
+    };
+    if let Err(err) = result {
         eprintln!("Error: {}", err);
     }
 }
 
-fn make_bot(args: Vec<String>, token: String) -> BotResult<Arc<Bot<ureq::Agent>>> {
-    let storage = storage::Storage::new(REDIS_URL)?;
+fn usage(program: &str) -> String {
+    format!(
+        r#"Usage: {0} behavior
+    where `behavior` is the id of a challenge in the quest file (`chest`, ...),
+    `stats` to reply to admins with the number of players who passed each check,
+    or `test` to reply with hashes of received images.
+The community token is read from the COMMUNITY_TOKEN environment variable.
+
+Usage: {0} run config.toml
+    to host several communities, each with its own token and behavior.
+
+Usage: {0} hash [--alg ALG] [--size WxH] image.jpg...
+    to print quest targets for the images (ALG is one of mean, gradient,
+    double_gradient or blockhash; double_gradient 15x15 by default).
+
+Usage: {0} verify quest.toml photos/
+    to report which targets each photo in the directory matches."#,
+        program
+    )
+}
+
+fn make_single_bot(
+    program: &str,
+    behavior: &str,
+    token: String,
+) -> BotResult<Arc<Bot<ureq::Agent>>> {
+    if behavior.is_empty() {
+        return Err(format!("No behavior specified.\n{}", usage(program)).into());
+    }
+    let quest = quest::Quest::load(&quest_path())?;
+    let storage = storage::Storage::open(&storage_url())?;
+    let behavior = make_behavior(behavior, storage.clone(), &quest, &admin_ids)?;
     let vk = VkApi::new(ureq::agent(), token)?;
-    let behavior: Box<dyn Behavior<ureq::Agent>> = match args.get(1).map(|a| a.as_str()) {
-        Some("chest") => Box::new(ChestBehavior::new(storage)),
-        Some("gates") => Box::new(GatesBehavior::new(storage)),
-        Some("stats") => Box::new(StatsBehavior::new(storage, admin_ids())),
-        Some("stone") => Box::new(StoneBehavior::new(storage, admin_ids())),
-        Some("test") => Box::new(TestBehavior::new()),
-        _ => {
-            return Err(format!(
-                r#"No behavior specified.
-Usage: {} behavior
-    where `behavior` is one of the challenges (`chest`, ...)
-    or `test` to reply with hashes of received images."#,
-                args[0]
-            )
-            .into())
-        }
+    Ok(Arc::new(Bot {
+        vk,
+        behavior,
+        storage,
+    }))
+}
+
+fn make_behavior(
+    name: &str,
+    storage: storage::Storage,
+    quest: &quest::Quest,
+    admin_ids: &dyn Fn() -> Vec<i64>,
+) -> BotResult<Box<dyn Behavior<ureq::Agent>>> {
+    Ok(match name {
+        "stats" => Box::new(StatsBehavior::new(storage, admin_ids(), quest.clone())),
+        "test" => Box::new(TestBehavior::new(quest.clone())?),
+        _ => match quest.challenges.get(name) {
+            Some(challenge) => Box::new(ChallengeBehavior::new(
+                name,
+                challenge.clone(),
+                storage,
+                admin_ids(),
+            )?),
+            None => {
+                return Err(format!(
+                    "Unknown behavior \"{}\": the quest file has no such challenge",
+                    name
+                )
+                .into())
+            }
+        },
+    })
+}
+
+fn run_communities(config: config::BotConfig) -> BotResult<()> {
+    let quest = quest::Quest::load(&config.quest.clone().unwrap_or_else(quest_path))?;
+    let storage = match config.storage {
+        Some(ref url) => storage::Storage::open(url)?,
+        None => storage::Storage::open(&storage_url())?,
     };
-    Ok(Arc::new(Bot { vk, behavior }))
+    let config_admin_ids = config.admin_ids;
+    let community_admin_ids = || config_admin_ids.clone().unwrap_or_else(admin_ids);
+
+    let mut bots = Vec::with_capacity(config.communities.len());
+    let mut callback_endpoints = Vec::new();
+    for community in config.communities.into_iter() {
+        let behavior = make_behavior(
+            &community.behavior,
+            storage.clone(),
+            &quest,
+            &community_admin_ids,
+        )?;
+        let vk = VkApi::new(ureq::agent(), community.token)?;
+        if let Some(callback) = community.callback {
+            callback_endpoints.push(VkCallbackEndpoint {
+                community_id: vk.community_id().to_owned(),
+                confirmation: callback.confirmation,
+                secret: callback.secret,
+            });
+        }
+        bots.push(Arc::new(Bot {
+            vk,
+            behavior,
+            storage: storage.clone(),
+        }));
+    }
+
+    // Each long polling community gets its own thread, communities using the Callback API
+    // share a single server; the first one to fail brings the process down
+    let workers = Arc::new(make_worker_pool());
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (callback_bots, long_poll_bots): (Vec<_>, Vec<_>) = bots.into_iter().partition(|bot| {
+        callback_endpoints
+            .iter()
+            .any(|e| e.community_id == bot.vk.community_id())
+    });
+    for bot in long_poll_bots {
+        let tx = tx.clone();
+        let workers = workers.clone();
+        std::thread::spawn(move || {
+            let desc = bot.to_string();
+            let result = run_bot(bot, workers).map_err(|e| format!("{}: {}", desc, e));
+            let _ = tx.send(result);
+        });
+    }
+    if let Some(addr) = config.callback_listen.filter(|_| !callback_bots.is_empty()) {
+        let server = VkCallbackServer::bind(&addr)?;
+        std::thread::spawn(move || {
+            let result = run_callback_server(server, callback_endpoints, callback_bots, workers)
+                .map_err(|e| format!("Callback server: {}", e));
+            let _ = tx.send(result);
+        });
+    }
+    rx.recv()
+        .map_err(|e| e.to_string())?
+        .map_err(BotError::from)
+}
+
+fn quest_path() -> PathBuf {
+    let path = env::var("SALMON_QUEST").unwrap_or_else(|_| DEFAULT_QUEST_PATH.into());
+    println!("Quest definition: {}", path);
+    path.into()
+}
+
+fn storage_url() -> String {
+    let url = env::var("SALMON_STORAGE").unwrap_or_else(|_| DEFAULT_STORAGE_URL.into());
+    println!("Storage: {}", url);
+    url
 }
 
 fn admin_ids() -> Vec<i64> {
@@ -75,21 +237,170 @@ fn admin_ids() -> Vec<i64> {
     admin_ids
 }
 
-fn run_bot(bot: Arc<Bot<ureq::Agent>>) -> BotResult<()> {
+fn make_worker_pool() -> WorkerPool {
+    let workers = env::var("SALMON_WORKERS")
+        .ok()
+        .and_then(|w| w.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_WORKERS);
+    println!("Message workers: {}", workers);
+    WorkerPool::new(workers, WORKER_QUEUE_CAPACITY)
+}
+
+fn run_bot(bot: Arc<Bot<ureq::Agent>>, workers: Arc<WorkerPool>) -> BotResult<()> {
     println!("{}", bot);
 
-    let mut lp = VkLongPoll::init(&bot.vk)?;
+    let ts_key = format!("{}_{}", STORAGE_LONG_POLL_TS, bot.vk.community_id());
+    let mut lp = match bot.storage.get(&ts_key)? {
+        Some(ts) => {
+            println!("Resuming long poll from ts {}", ts);
+            VkLongPoll::resume(&bot.vk, ts)?
+        }
+        None => VkLongPoll::init(&bot.vk)?,
+    };
+    // Responses whose updates are still being processed by the workers, oldest first
+    let mut pending = VecDeque::new();
+    let mut failures = 0;
     loop {
-        lp.poll_once(|msg| spawn_message_handler(bot.clone(), msg))?;
+        let batch = Batch::new();
+        let result =
+            lp.poll_once(|event| dispatch_event(&workers, Some(&batch), bot.clone(), event));
+        if result.is_ok() {
+            pending.push_back((lp.ts().to_owned(), batch.seal()));
+        }
+        let result = result.and_then(|_| save_checkpoint(&bot.storage, &ts_key, &mut pending));
+        let err = match result {
+            Ok(()) => {
+                failures = 0;
+                continue;
+            }
+            Err(e) => e,
+        };
+        eprintln!("Long poll error: {}", err);
+        let delay = match err.policy() {
+            ErrorPolicy::Shutdown => return Err(err),
+            ErrorPolicy::Retry => {
+                failures += 1;
+                std::cmp::min(
+                    MSG_DELAY_RETRY * 2u32.pow(std::cmp::min(failures, 5)),
+                    LONG_POLL_MAX_BACKOFF,
+                )
+            }
+            // Nobody to apologize to, the updates will be requested again
+            ErrorPolicy::Skip | ErrorPolicy::Apologize => MSG_DELAY_RETRY,
+        };
+        eprintln!("Polling again in {:?}", delay);
+        std::thread::sleep(delay);
     }
 }
 
-fn spawn_message_handler<C: Client>(bot: Arc<Bot<C>>, msg: VkMessage) {
-    std::thread::spawn(move || {
-        if let Err(e) = bot.behavior.process_on_own_thread(&bot.vk, &msg) {
-            eprintln!("Error when processing {:?}: {}", msg, e);
-            eprintln!("Initiating hard shutdown, how do you like THAT Elon Musk?");
-            std::process::exit(1);
+/// Saves the ts of the newest response whose updates have been processed, along with
+/// the updates of every response before it. A restart then picks up messages that arrived
+/// while the bot was down or were still waiting for a worker, while polling never waits
+/// for slow conversations.
+fn save_checkpoint(
+    storage: &storage::Storage,
+    ts_key: &str,
+    pending: &mut VecDeque<(String, SealedBatch)>,
+) -> BotResult<()> {
+    let mut processed = None;
+    while pending.front().map_or(false, |(_, batch)| batch.is_done()) {
+        processed = pending.pop_front().map(|(ts, _)| ts);
+    }
+    match processed {
+        Some(ts) => storage.set(ts_key, &ts),
+        None => Ok(()),
+    }
+}
+
+fn run_callback_server(
+    server: VkCallbackServer,
+    endpoints: Vec<VkCallbackEndpoint>,
+    bots: Vec<Arc<Bot<ureq::Agent>>>,
+    workers: Arc<WorkerPool>,
+) -> BotResult<()> {
+    for bot in bots.iter() {
+        println!("{}", bot);
+    }
+    println!(
+        "Listening for Callback API requests on {}",
+        server.local_addr()
+    );
+
+    server.serve(&endpoints, |community_id, event| {
+        if let Some(bot) = bots.iter().find(|b| b.vk.community_id() == community_id) {
+            dispatch_event(&workers, None, bot.clone(), event);
         }
-    });
+    })
+}
+
+/// Events from the same conversation are processed sequentially, in the order they were received,
+/// so that the members of a team playing in a group chat do not race each other
+fn dispatch_event<C: Client>(
+    workers: &WorkerPool,
+    batch: Option<&Batch>,
+    bot: Arc<Bot<C>>,
+    event: VkEvent,
+) {
+    let peer_id = event.peer_id();
+    let submit = |job: Box<dyn FnOnce() + Send>| match batch {
+        Some(batch) => workers.submit(peer_id, batch.track(job)),
+        None => workers.submit(peer_id, job),
+    };
+    match event {
+        VkEvent::MessageNew(mut msg) => {
+            // Group chats are full of messages meant for other players
+            if msg.is_group_chat()
+                && !msg.strip_mention(bot.vk.community_id())
+                && msg.all_photos().is_empty()
+            {
+                return;
+            }
+            submit(Box::new(move || {
+                handle_event(&bot, msg.peer_id, &msg, || {
+                    bot.behavior.process_on_own_thread(&bot.vk, &msg)
+                })
+            }))
+        }
+        VkEvent::MessageEvent(event) => submit(Box::new(move || {
+            handle_event(&bot, event.peer_id, &event, || {
+                bot.behavior.process_event_on_own_thread(&bot.vk, &event)
+            })
+        })),
+        // Edits, subscriptions and community membership do not affect the quest
+        _ => (),
+    }
+}
+
+/// Failures that cannot be retried are apologized for in the conversation `peer_id`
+fn handle_event<C, F>(bot: &Bot<C>, peer_id: i64, event: &dyn std::fmt::Debug, process: F)
+where
+    C: Client,
+    F: Fn() -> ThreadResult,
+{
+    let mut retries = 0;
+    loop {
+        let err = match process() {
+            Ok(()) => return,
+            Err(e) => e,
+        };
+        eprintln!("Error when processing {:?}: {}", event, err);
+        match err.policy() {
+            ErrorPolicy::Retry if retries < MSG_MAX_RETRIES => {
+                retries += 1;
+                eprintln!("Retrying ({}/{})", retries, MSG_MAX_RETRIES);
+                std::thread::sleep(MSG_DELAY_RETRY * retries as u32);
+            }
+            ErrorPolicy::Retry | ErrorPolicy::Apologize => {
+                if let Err(e) = bot.vk.send(peer_id, APOLOGY_TEXT, None, None) {
+                    eprintln!("Unable to apologize to {}: {}", peer_id, e);
+                }
+                return;
+            }
+            ErrorPolicy::Skip => return,
+            ErrorPolicy::Shutdown => {
+                eprintln!("Initiating hard shutdown, how do you like THAT Elon Musk?");
+                std::process::exit(1);
+            }
+        }
+    }
 }
@@ -0,0 +1,668 @@
+use crate::answer_match::AnswerMatcher;
+use crate::img_match::{
+    HashAlgorithm, HashConfig, VariantKind, DEFAULT_HASH_SIZE, HAMMING_TOLERANCE,
+};
+use crate::template::Template;
+use crate::vkapi::VkQuote;
+use crate::BotResult;
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Quest {
+    /// What to do if targets of a challenge are close enough to be mistaken for each other
+    #[serde(default)]
+    pub collisions: CollisionPolicy,
+    /// Keyed by id, which is also the behavior name used to run the challenge
+    #[serde(default)]
+    pub challenges: BTreeMap<String, Challenge>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    Warn,
+    Refuse,
+}
+
+impl Default for CollisionPolicy {
+    fn default() -> Self {
+        CollisionPolicy::Warn
+    }
+}
+
+/// Two targets with hashes within their combined tolerance of each other,
+/// meaning that a single image may match both
+#[derive(Debug, PartialEq)]
+pub struct TargetCollision<'a> {
+    pub first: &'a str,
+    pub second: &'a str,
+    pub distance: u64,
+    pub tolerance: u64,
+}
+
+impl std::fmt::Display for TargetCollision<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} and {}: distance {} (combined tolerance {})",
+            self.first, self.second, self.distance, self.tolerance
+        )
+    }
+}
+
+/// A sequence of stages a player goes through, one at a time
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Challenge {
+    /// Shown in stats; the id is used if there is none
+    pub title: Option<String>,
+    pub stages: Vec<Stage>,
+    /// Submitted photos are downloaded in the smallest size at least this wide
+    #[serde(default)]
+    pub image_width: u64,
+    /// Whether replies quote or forward the player's message
+    pub quote: Option<VkQuote>,
+}
+
+/// A stage is completed once the player has passed the required number of its checks:
+/// image targets, text answers and shared locations. Checks are passed one message at a time,
+/// and each is only counted once.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Stage {
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub answers: Vec<Answer>,
+    #[serde(default)]
+    pub locations: Vec<Location>,
+    /// How many checks must be passed to complete the stage; all by default
+    pub required: Option<usize>,
+    /// Checks only count when passed in order: targets, then answers, then locations,
+    /// each in the order they are listed
+    #[serde(default)]
+    pub ordered: bool,
+    pub completion_text: String,
+    pub completion_image: Option<QuestImage>,
+    /// A button under the completion text, e.g. leading to the next challenge
+    pub completion_link: Option<QuestLink>,
+    /// Sent when a message passes no new checks; the player's progress is sent by default
+    pub fail_text: Option<String>,
+    /// Sent when a message passes new checks but the stage is not completed yet
+    pub progress_text: Option<String>,
+    /// Shown in a pop-up when the player presses the button under the replies to them
+    /// while on this stage, up to 90 characters
+    pub hint: Option<String>,
+    /// Sent instead of `fail_text` to players on this stage who pass no check of it,
+    /// but pass a check from another stage
+    pub wrong_stage_text: Option<String>,
+}
+
+/// Messages are compared to answers after normalising case, whitespace and ё
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Answer {
+    pub name: String,
+    pub answer: Option<String>,
+    /// Other accepted answers
+    #[serde(default)]
+    pub synonyms: Vec<String>,
+    /// A regular expression the whole message must match, ignoring case; ё in the pattern
+    /// matches е, like in normalized messages
+    pub pattern: Option<String>,
+    /// How many typos (single-character edits) an accepted answer may have
+    #[serde(default)]
+    pub typos: usize,
+    pub hint: Option<AnswerHint>,
+}
+
+/// Sent in reply to wrong answers that are close to an accepted one
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnswerHint {
+    pub text: String,
+    /// The maximum number of single-character edits from an accepted answer
+    pub distance: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// How far from the point the shared location may be, in meters
+    pub radius: f64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuestLink {
+    pub label: String,
+    pub url: String,
+}
+
+/// Variables available in the replies of a stage, see `Template`
+pub const STAGE_TEMPLATE_VARIABLES: &[&str] = &[
+    // The first name of the player who sent the message
+    "name",
+    // The number of the player's stage and the total number of stages
+    "stage",
+    "stages",
+    // Checks passed on the stage, needed to complete it, and still needed
+    "passed",
+    "required",
+    "remaining",
+];
+
+const DEFAULT_PROGRESS_TEXT: &str = "{passed}/{required}";
+
+impl Challenge {
+    pub fn title<'a>(&'a self, id: &'a str) -> &'a str {
+        self.title.as_ref().map_or(id, |t| t.as_str())
+    }
+}
+
+impl Stage {
+    /// Names of the checks in order: targets, then answers, then locations
+    pub fn check_names(&self) -> Vec<&str> {
+        let targets = self.targets.iter().map(|t| t.name.as_str());
+        let answers = self.answers.iter().map(|a| a.name.as_str());
+        let locations = self.locations.iter().map(|l| l.name.as_str());
+        targets.chain(answers).chain(locations).collect()
+    }
+
+    pub fn required(&self) -> usize {
+        self.required
+            .unwrap_or(self.targets.len() + self.answers.len() + self.locations.len())
+    }
+
+    pub fn progress_text(&self) -> &str {
+        self.progress_text
+            .as_deref()
+            .unwrap_or(DEFAULT_PROGRESS_TEXT)
+    }
+
+    /// Replies that are rendered as templates
+    pub fn templates(&self) -> Vec<&str> {
+        let mut templates = vec![self.completion_text.as_str(), self.progress_text()];
+        templates.extend(self.fail_text.as_deref());
+        templates.extend(self.wrong_stage_text.as_deref());
+        templates
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    pub name: String,
+    pub hash: Vec<u8>,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: u64,
+    /// The algorithm the hash was computed with; images are hashed with it before comparison
+    #[serde(default)]
+    pub alg: HashAlgorithm,
+    #[serde(default = "default_hash_size")]
+    pub hash_size: [u32; 2],
+    /// DCT preprocessing; enabled by default for all algorithms except blockhash
+    pub dct: Option<bool>,
+    /// Hashes of the same image computed with other algorithms, to tell similar images apart
+    #[serde(default)]
+    pub extra_hashes: Vec<TargetHash>,
+    /// How many of the hashes must be within tolerance for the image to match; all by default
+    pub consensus: Option<usize>,
+    /// Rotated, mirrored or cropped versions of submitted images to compare as well
+    #[serde(default)]
+    pub variants: Vec<VariantKind>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetHash {
+    pub hash: Vec<u8>,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: u64,
+    #[serde(default)]
+    pub alg: HashAlgorithm,
+    #[serde(default = "default_hash_size")]
+    pub hash_size: [u32; 2],
+    pub dct: Option<bool>,
+}
+
+fn default_tolerance() -> u64 {
+    HAMMING_TOLERANCE
+}
+
+fn default_hash_size() -> [u32; 2] {
+    DEFAULT_HASH_SIZE
+}
+
+impl Target {
+    /// The main hash followed by `extra_hashes`
+    pub fn hashes(&self) -> Vec<TargetHash> {
+        let main = TargetHash {
+            hash: self.hash.clone(),
+            tolerance: self.tolerance,
+            alg: self.alg,
+            hash_size: self.hash_size,
+            dct: self.dct,
+        };
+        let mut hashes = vec![main];
+        hashes.extend(self.extra_hashes.iter().cloned());
+        hashes
+    }
+
+    pub fn consensus(&self) -> usize {
+        self.consensus.unwrap_or(1 + self.extra_hashes.len())
+    }
+}
+
+impl TargetHash {
+    pub fn hash_config(&self) -> HashConfig {
+        HashConfig {
+            alg: self.alg,
+            width: self.hash_size[0],
+            height: self.hash_size[1],
+            dct: self
+                .dct
+                .unwrap_or_else(|| self.alg != HashAlgorithm::Blockhash),
+        }
+    }
+}
+
+/// An image referenced by path in the quest file, read into memory when the quest is loaded
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "PathBuf")]
+pub struct QuestImage {
+    pub path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl From<PathBuf> for QuestImage {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl QuestImage {
+    /// Image data with the file extension, as expected by `VkPhotosApi::upload_message_photo`
+    pub fn as_upload(&self) -> (&[u8], &str) {
+        let ext = self
+            .path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("jpg");
+        (&self.data, ext)
+    }
+
+    fn load(&mut self, base_dir: &Path) -> BotResult<()> {
+        self.path = base_dir.join(&self.path);
+        self.data = std::fs::read(&self.path)
+            .map_err(|e| format!("Cannot read {}: {}", self.path.display(), e))?;
+        Ok(())
+    }
+}
+
+impl Quest {
+    pub fn load(path: &Path) -> BotResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read quest file {}: {}", path.display(), e))?;
+        let mut quest: Quest = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .map_err(|e| format!("Invalid quest file {}: {}", path.display(), e))?,
+            _ => toml::from_str(&contents)
+                .map_err(|e| format!("Invalid quest file {}: {}", path.display(), e))?,
+        };
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+        quest.load_images(base_dir)?;
+        quest.validate()?;
+        quest.check_collisions()?;
+        Ok(quest)
+    }
+
+    /// All image targets across the quest, in the order they are defined
+    pub fn image_targets(&self) -> Vec<&Target> {
+        self.challenges
+            .values()
+            .flat_map(|c| c.stages.iter())
+            .flat_map(|s| s.targets.iter())
+            .collect()
+    }
+
+    fn load_images(&mut self, base_dir: &Path) -> BotResult<()> {
+        for challenge in self.challenges.values_mut() {
+            for stage in challenge.stages.iter_mut() {
+                if let Some(ref mut image) = stage.completion_image {
+                    image.load(base_dir)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pairs of targets of the same challenge an image may match at the same time, closest first.
+    /// Each target is a separate check, so any such pair is worth a second look.
+    pub fn target_collisions(&self) -> Vec<TargetCollision<'_>> {
+        let mut collisions = self
+            .challenges
+            .values()
+            .flat_map(|c| {
+                let targets = c.stages.iter().flat_map(|s| s.targets.iter()).collect();
+                challenge_target_collisions(targets)
+            })
+            .collect::<Vec<_>>();
+        collisions.sort_by_key(|c| c.distance);
+        collisions
+    }
+
+    fn check_collisions(&self) -> BotResult<()> {
+        let collisions = self.target_collisions();
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        let list = collisions
+            .iter()
+            .map(|c| format!("\n  {}", c))
+            .collect::<String>();
+        match self.collisions {
+            CollisionPolicy::Warn => {
+                println!(
+                    "Warning: some targets may be mistaken for each other:{}",
+                    list
+                );
+                Ok(())
+            }
+            CollisionPolicy::Refuse => Err(format!(
+                "Some targets may be mistaken for each other (set `collisions = \"warn\"` \
+                 in the quest file to start anyway):{}",
+                list
+            )
+            .into()),
+        }
+    }
+
+    fn validate(&self) -> BotResult<()> {
+        for (id, challenge) in self.challenges.iter() {
+            if challenge.stages.is_empty() {
+                return Err(format!("Challenge {}: at least one stage is required", id).into());
+            }
+            // Each check has its own storage bucket, named after the check
+            let mut names = HashSet::new();
+            for (i, stage) in challenge.stages.iter().enumerate() {
+                let checks = stage.check_names();
+                if checks.is_empty() {
+                    return Err(format!("Challenge {}: stage {} has no checks", id, i + 1).into());
+                }
+                if stage.required() == 0 || stage.required() > checks.len() {
+                    return Err(format!(
+                        "Challenge {}: stage {} must require between 1 and {} checks",
+                        id,
+                        i + 1,
+                        checks.len()
+                    )
+                    .into());
+                }
+                for name in checks {
+                    if !names.insert(name) {
+                        return Err(format!(
+                            "Challenge {}: check {} is defined more than once",
+                            id, name
+                        )
+                        .into());
+                    }
+                }
+                // Hints are shown in a snackbar, which VK limits to 90 characters
+                if stage
+                    .hint
+                    .as_ref()
+                    .map_or(false, |h| h.chars().count() > 90)
+                {
+                    return Err(format!(
+                        "Challenge {}: the hint of stage {} is longer than 90 characters",
+                        id,
+                        i + 1
+                    )
+                    .into());
+                }
+                for source in stage.templates() {
+                    let template = Template::parse(source)
+                        .map_err(|e| format!("Challenge {}: stage {}: {}", id, i + 1, e))?;
+                    let unknown = template
+                        .variables()
+                        .find(|v| !STAGE_TEMPLATE_VARIABLES.contains(v));
+                    if let Some(unknown) = unknown {
+                        return Err(format!(
+                            "Challenge {}: stage {}: unknown variable {{{}}} in \"{}\"",
+                            id,
+                            i + 1,
+                            unknown,
+                            source
+                        )
+                        .into());
+                    }
+                }
+                for answer in stage.answers.iter() {
+                    AnswerMatcher::new(answer).map_err(|e| format!("Challenge {}: {}", id, e))?;
+                }
+                for location in stage.locations.iter() {
+                    if location.latitude.abs() > 90.0 || location.longitude.abs() > 180.0 {
+                        return Err(format!(
+                            "Challenge {}: the coordinates of {} are out of range",
+                            id, location.name
+                        )
+                        .into());
+                    }
+                    if location.radius <= 0.0 {
+                        return Err(format!(
+                            "Challenge {}: the radius of {} must be positive",
+                            id, location.name
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+
+        let targets = self.image_targets();
+        for target in targets.iter() {
+            let hashes = target.hashes();
+            if target.consensus() == 0 || target.consensus() > hashes.len() {
+                return Err(format!(
+                    "Target {}: consensus must be between 1 and the number of hashes ({})",
+                    target.name,
+                    hashes.len()
+                )
+                .into());
+            }
+            for hash in hashes.iter() {
+                if hash.hash.is_empty() {
+                    return Err(format!("Target {} has an empty hash", target.name).into());
+                }
+                if hash.hash_size[0] == 0 || hash.hash_size[1] == 0 {
+                    return Err(format!("Target {} has a zero hash size", target.name).into());
+                }
+                if hash.alg == HashAlgorithm::Blockhash && hash.dct == Some(true) {
+                    return Err(format!(
+                        "Target {}: DCT preprocessing is not supported by blockhash",
+                        target.name
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn challenge_target_collisions(targets: Vec<&Target>) -> Vec<TargetCollision<'_>> {
+    let hashes = targets.iter().map(|t| t.hashes()).collect::<Vec<_>>();
+    let mut collisions = Vec::new();
+    for i in 0..targets.len() {
+        for j in (i + 1)..targets.len() {
+            let closest = hashes[i]
+                .iter()
+                .flat_map(|a| hashes[j].iter().map(move |b| (a, b)))
+                .filter(|(a, b)| a.hash_config() == b.hash_config() && a.hash.len() == b.hash.len())
+                .map(|(a, b)| {
+                    let distance = hamming::distance(&a.hash, &b.hash);
+                    (distance, a.tolerance + b.tolerance)
+                })
+                .filter(|(distance, tolerance)| distance <= tolerance)
+                .min();
+            if let Some((distance, tolerance)) = closest {
+                collisions.push(TargetCollision {
+                    first: &targets[i].name,
+                    second: &targets[j].name,
+                    distance,
+                    tolerance,
+                });
+            }
+        }
+    }
+    collisions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_quest() {
+        let path = format!("{}/tests/fixtures/quest.toml", env!("CARGO_MANIFEST_DIR"));
+        let quest = Quest::load(Path::new(&path)).unwrap();
+
+        let chest = &quest.challenges["chest"];
+        assert_eq!(chest.title("chest"), "Сундук");
+        let wrench = &chest.stages[0].targets[0];
+        assert_eq!(wrench.name, "wrench");
+        assert_eq!(wrench.tolerance, HAMMING_TOLERANCE);
+        let success_image = chest.stages[0].completion_image.as_ref().unwrap();
+        assert_eq!(success_image.as_upload().1, "jpg");
+        assert!(!success_image.as_upload().0.is_empty());
+
+        let gates = &quest.challenges["gates"].stages[0];
+        assert_eq!(gates.answers[0].answer.as_deref(), Some("679823154"));
+        assert!(gates.completion_image.is_none());
+
+        let location = &quest.challenges["checkpoint"].stages[0].locations[0];
+        assert_eq!(
+            (location.latitude, location.longitude, location.radius),
+            (59.9398, 30.3146, 50.0)
+        );
+
+        let stone = &quest.challenges["stone"];
+        assert_eq!(stone.stages.len(), 2);
+        assert_eq!(stone.stages[0].required(), 2);
+        assert_eq!(stone.stages[1].required(), 1);
+        assert_eq!(stone.stages[1].check_names(), vec!["2-а", "2-слово"]);
+        assert_eq!(stone.stages[1].targets[0].tolerance, 10);
+        assert_eq!(stone.stages[1].targets[0].hashes().len(), 2);
+        assert_eq!(stone.stages[1].targets[0].consensus(), 1);
+        assert_eq!(stone.stages[0].targets[0].consensus(), 1);
+        assert_eq!(
+            stone.stages[0].targets[0].hashes()[0].hash_config(),
+            HashConfig::default()
+        );
+        let blockhash = stone.stages[0].targets[1].hashes()[0].hash_config();
+        assert_eq!(blockhash.alg, HashAlgorithm::Blockhash);
+        assert_eq!(
+            (blockhash.width, blockhash.height, blockhash.dct),
+            (12, 12, false)
+        );
+
+        let names = quest
+            .image_targets()
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["wrench", "1-а", "1-б", "2-а"]);
+    }
+
+    #[test]
+    fn test_target_collisions() {
+        let mut quest: Quest = toml::from_str(
+            r#"
+            collisions = "refuse"
+
+            [[challenges.stone.stages]]
+            completion_text = "1"
+            targets = [
+                { name = "a", hash = [0, 0] },
+                { name = "b", hash = [0, 255], tolerance = 1 },
+                { name = "c", hash = [0, 0] },
+            ]
+
+            [[challenges.stone.stages]]
+            completion_text = "2"
+            targets = [{ name = "d", hash = [0, 7], tolerance = 0 }]
+
+            # targets of different challenges never collide
+            [[challenges.chest.stages]]
+            completion_text = "3"
+            targets = [{ name = "e", hash = [0, 0] }]
+            "#,
+        )
+        .unwrap();
+        let collisions = quest.target_collisions();
+        assert_eq!(
+            collisions.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+            vec![
+                "a and c: distance 0 (combined tolerance 14)",
+                "a and d: distance 3 (combined tolerance 7)",
+                "c and d: distance 3 (combined tolerance 7)",
+                "a and b: distance 8 (combined tolerance 8)",
+                "b and c: distance 8 (combined tolerance 8)",
+            ]
+        );
+        assert!(quest.check_collisions().is_err());
+        quest.collisions = CollisionPolicy::Warn;
+        assert!(quest.check_collisions().is_ok());
+    }
+
+    #[test]
+    fn test_reject_duplicate_checks() {
+        let mut quest: Quest = toml::from_str(
+            r#"
+            [[challenges.stone.stages]]
+            completion_text = "1"
+            completion_image = "test.jpg"
+            targets = [{ name = "a", hash = [1, 2] }]
+
+            [[challenges.stone.stages]]
+            completion_text = "2"
+            answers = [{ name = "a", answer = "a" }]
+            "#,
+        )
+        .unwrap();
+        let fixtures = format!("{}/tests/fixtures", env!("CARGO_MANIFEST_DIR"));
+        quest.load_images(Path::new(&fixtures)).unwrap();
+        let err = quest.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Challenge stone: check a is defined more than once"
+        );
+
+        quest.challenges.get_mut("stone").unwrap().stages[1].required = Some(2);
+        let err = quest.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Challenge stone: stage 2 must require between 1 and 1 checks"
+        );
+
+        let stages = &mut quest.challenges.get_mut("stone").unwrap().stages;
+        stages[1].required = None;
+        stages[1].answers[0].name = "b".into();
+        stages[1].fail_text = Some("{player}, попробуй еще".into());
+        let err = quest.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Challenge stone: stage 2: unknown variable {player} in \"{player}, попробуй еще\""
+        );
+    }
+}
@@ -1,106 +1,155 @@
 use crate::BotResult;
-use redis::Commands;
-use std::{ops::DerefMut, sync::Mutex};
+use std::sync::Arc;
 
-pub type StorageResult<'s, T> = Result<T, Box<dyn std::error::Error + 's>>;
+mod memory;
+mod redis;
+mod sqlite;
+pub use self::memory::MemoryStorage;
+pub use self::redis::RedisStorage;
+pub use self::sqlite::SqliteStorage;
 
-pub struct Storage {
-    redis: Mutex<redis::Connection>,
+pub type StorageResult<T> = BotResult<T>;
+
+/// A set of players: user ids, or peer ids of group chats playing as a team
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PlayerSet {
+    /// Players who have completed all stages of the challenge
+    Completed { challenge: String },
+    /// Players who have passed the check of the challenge
+    CheckBucket { challenge: String, check: String },
 }
 
-impl Storage {
-    pub fn new(redis_url: &str) -> BotResult<Self> {
-        let redis = redis::Client::open(redis_url)
-            .and_then(|c| c.get_connection())
-            .map_err(|e| format!("Redis: {}", e))?;
-
-        Ok(Self {
-            redis: Mutex::new(redis),
-        })
+impl PlayerSet {
+    pub fn completed(challenge: &str) -> Self {
+        PlayerSet::Completed {
+            challenge: challenge.to_owned(),
+        }
     }
 
-    pub fn set_add<'s, V: redis::ToRedisArgs + std::fmt::Display + Copy>(
-        &'s self,
-        set: &str,
-        value: V,
-    ) -> StorageResult<'s, ()> {
-        let mut conn = self.redis.lock()?;
-        conn.sadd(set, value)
-            .map_err(|e| format!("Cannot add {} to {}: {}", value, set, e).into())
+    pub fn check_bucket(challenge: &str, check: &str) -> Self {
+        PlayerSet::CheckBucket {
+            challenge: challenge.to_owned(),
+            check: check.to_owned(),
+        }
     }
+}
 
-    pub fn set_contains<'s, V: redis::ToRedisArgs + std::fmt::Display + Copy>(
-        &'s self,
-        set: &str,
-        value: V,
-    ) -> StorageResult<'s, bool> {
-        let mut conn = self.redis.lock()?;
-        conn.sismember(set, value)
-            .map_err(|e| format!("Cannot check membership of {} in {}: {}", value, set, e).into())
+impl std::fmt::Display for PlayerSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerSet::Completed { challenge } => write!(f, "{} completions", challenge),
+            PlayerSet::CheckBucket { challenge, check } => {
+                write!(f, "{} check {}", challenge, check)
+            }
+        }
     }
+}
 
-    pub fn sets_add_and_count_containing<'s, V: redis::ToRedisArgs + std::fmt::Display + Copy>(
-        &'s self,
-        add_to_sets: &[String],
-        count_in_sets: &[String],
-        value: V,
-    ) -> StorageResult<'s, usize> {
-        let mut pipe = redis::pipe();
-        pipe.atomic();
-        for set in add_to_sets {
-            pipe.sadd(set, value).ignore();
-        }
-        for set in count_in_sets {
-            pipe.sismember(set, value);
-        }
-        let mut conn = self.redis.lock()?;
-        pipe.query::<Vec<bool>>(conn.deref_mut())
-            .map(|r| r.iter().filter(|ismem| **ismem).count())
-            .map_err(|e| {
-                format!(
-                    "Cannot add {} to sets {} with membership check across {}: {}",
-                    value,
-                    add_to_sets.join(","),
-                    count_in_sets.join(","),
-                    e
-                )
-                .into()
+pub trait StorageBackend: Send + Sync {
+    fn get(&self, key: &str) -> StorageResult<Option<String>>;
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()>;
+
+    fn set_add(&self, set: &PlayerSet, player: i64) -> StorageResult<()>;
+
+    fn set_contains(&self, set: &PlayerSet, player: i64) -> StorageResult<bool>;
+
+    fn set_remove(&self, set: &PlayerSet, player: i64) -> StorageResult<()>;
+
+    /// Adds `player` to each of `add_to_sets`, then counts how many of `count_in_sets` contain it.
+    /// Both steps are performed atomically.
+    fn sets_add_and_count_containing(
+        &self,
+        add_to_sets: &[PlayerSet],
+        count_in_sets: &[PlayerSet],
+        player: i64,
+    ) -> StorageResult<usize>;
+
+    fn sets_len(&self, sets: &[PlayerSet]) -> StorageResult<Vec<u64>>;
+
+    /// The index of the stage the player is on; players start at 0
+    fn stage(&self, challenge: &str, player: i64) -> StorageResult<usize>;
+
+    fn set_stage(&self, challenge: &str, player: i64, stage: usize) -> StorageResult<()>;
+
+    /// Moves the player to the next stage
+    fn advance_stage(&self, challenge: &str, player: i64) -> StorageResult<()>;
+}
+
+/// A handle to the storage backend, cheaply cloneable to be shared between behaviors
+#[derive(Clone)]
+pub struct Storage {
+    backend: Arc<dyn StorageBackend>,
+}
+
+impl Storage {
+    /// Opens a storage backend by URL: `redis://...`, `sqlite://path/to/file.db` or `memory:`
+    pub fn open(url: &str) -> BotResult<Self> {
+        if url.starts_with("redis://") {
+            Ok(Self {
+                backend: Arc::new(RedisStorage::new(url)?),
             })
+        } else if url.starts_with("sqlite://") {
+            Ok(Self {
+                backend: Arc::new(SqliteStorage::new(&url["sqlite://".len()..])?),
+            })
+        } else if url == "memory:" {
+            println!("Warning: using in-memory storage, all progress will be lost on restart");
+            Ok(Self::in_memory())
+        } else {
+            Err(format!("Unsupported storage URL: {}", url).into())
+        }
     }
 
-    pub fn sets_len<'s, S: AsRef<str>, I: IntoIterator<Item = S>>(
-        &'s self,
-        sets: I,
-    ) -> StorageResult<'s, Vec<u64>> {
-        let mut pipe = redis::pipe();
-        pipe.atomic();
-        for set in sets {
-            pipe.scard(set.as_ref());
+    pub fn in_memory() -> Self {
+        Self {
+            backend: Arc::new(MemoryStorage::new()),
         }
-        let mut conn = self.redis.lock()?;
-        pipe.query::<Vec<u64>>(conn.deref_mut())
-            .map_err(|e| format!("Cannot lookup set cardinality: {}", e).into())
     }
 
-    pub fn hash_set<'s, F: redis::ToRedisArgs + std::fmt::Display + Copy>(
-        &'s self,
-        hash: &str,
-        field: F,
-        value: u64,
-    ) -> StorageResult<'s, i64> {
-        let mut conn = self.redis.lock()?;
-        conn.hset(hash, field, value)
-            .map_err(|e| format!("Cannot set {}[{}] to {}: {}", hash, field, value, e).into())
+    pub fn get(&self, key: &str) -> StorageResult<Option<String>> {
+        self.backend.get(key)
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        self.backend.set(key, value)
+    }
+
+    pub fn set_add(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        self.backend.set_add(set, player)
+    }
+
+    pub fn set_contains(&self, set: &PlayerSet, player: i64) -> StorageResult<bool> {
+        self.backend.set_contains(set, player)
+    }
+
+    pub fn set_remove(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        self.backend.set_remove(set, player)
+    }
+
+    pub fn sets_add_and_count_containing(
+        &self,
+        add_to_sets: &[PlayerSet],
+        count_in_sets: &[PlayerSet],
+        player: i64,
+    ) -> StorageResult<usize> {
+        self.backend
+            .sets_add_and_count_containing(add_to_sets, count_in_sets, player)
+    }
+
+    pub fn sets_len(&self, sets: &[PlayerSet]) -> StorageResult<Vec<u64>> {
+        self.backend.sets_len(sets)
+    }
+
+    pub fn stage(&self, challenge: &str, player: i64) -> StorageResult<usize> {
+        self.backend.stage(challenge, player)
+    }
+
+    pub fn set_stage(&self, challenge: &str, player: i64, stage: usize) -> StorageResult<()> {
+        self.backend.set_stage(challenge, player, stage)
     }
 
-    pub fn hash_incr<'s, F: redis::ToRedisArgs + std::fmt::Display + Copy>(
-        &'s self,
-        hash: &str,
-        field: F,
-        delta: i64,
-    ) -> StorageResult<'s, i64> {
-        let mut conn = self.redis.lock()?;
-        conn.hincr(hash, field, delta)
-            .map_err(|e| format!("Cannot increment {}[{}] by {}: {}", hash, field, delta, e).into())
+    pub fn advance_stage(&self, challenge: &str, player: i64) -> StorageResult<()> {
+        self.backend.advance_stage(challenge, player)
     }
 }
@@ -0,0 +1,147 @@
+use crate::storage::{PlayerSet, StorageBackend, StorageResult};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Keeps everything in process memory: handy for development and tests,
+/// but all progress is lost when the bot is restarted.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    values: HashMap<String, String>,
+    sets: HashMap<PlayerSet, HashSet<i64>>,
+    /// Stages by challenge and player
+    stages: HashMap<(String, i64), usize>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn get(&self, key: &str) -> StorageResult<Option<String>> {
+        let data = self.data.lock()?;
+        Ok(data.values.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        let mut data = self.data.lock()?;
+        data.values.insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn set_add(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let mut data = self.data.lock()?;
+        data.sets.entry(set.clone()).or_default().insert(player);
+        Ok(())
+    }
+
+    fn set_contains(&self, set: &PlayerSet, player: i64) -> StorageResult<bool> {
+        let data = self.data.lock()?;
+        Ok(data.sets.get(set).map_or(false, |s| s.contains(&player)))
+    }
+
+    fn set_remove(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let mut data = self.data.lock()?;
+        if let Some(s) = data.sets.get_mut(set) {
+            s.remove(&player);
+        }
+        Ok(())
+    }
+
+    fn sets_add_and_count_containing(
+        &self,
+        add_to_sets: &[PlayerSet],
+        count_in_sets: &[PlayerSet],
+        player: i64,
+    ) -> StorageResult<usize> {
+        let mut data = self.data.lock()?;
+        for set in add_to_sets {
+            data.sets.entry(set.clone()).or_default().insert(player);
+        }
+        Ok(count_in_sets
+            .iter()
+            .filter(|set| data.sets.get(*set).map_or(false, |s| s.contains(&player)))
+            .count())
+    }
+
+    fn sets_len(&self, sets: &[PlayerSet]) -> StorageResult<Vec<u64>> {
+        let data = self.data.lock()?;
+        Ok(sets
+            .iter()
+            .map(|set| data.sets.get(set).map_or(0, |s| s.len() as u64))
+            .collect())
+    }
+
+    fn stage(&self, challenge: &str, player: i64) -> StorageResult<usize> {
+        let data = self.data.lock()?;
+        let stage = data.stages.get(&(challenge.to_owned(), player));
+        Ok(stage.cloned().unwrap_or(0))
+    }
+
+    fn set_stage(&self, challenge: &str, player: i64, stage: usize) -> StorageResult<()> {
+        let mut data = self.data.lock()?;
+        data.stages.insert((challenge.to_owned(), player), stage);
+        Ok(())
+    }
+
+    fn advance_stage(&self, challenge: &str, player: i64) -> StorageResult<()> {
+        let mut data = self.data.lock()?;
+        *data
+            .stages
+            .entry((challenge.to_owned(), player))
+            .or_insert(0) += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sets() {
+        let storage = MemoryStorage::new();
+        let a = PlayerSet::completed("a");
+        let b = PlayerSet::check_bucket("a", "b");
+        let c = PlayerSet::check_bucket("a", "c");
+        storage.set_add(&a, 1).unwrap();
+        storage.set_add(&a, 1).unwrap();
+        storage.set_add(&b, 2).unwrap();
+        assert!(storage.set_contains(&a, 1).unwrap());
+        assert!(!storage.set_contains(&a, 2).unwrap());
+        assert!(!storage.set_contains(&c, 1).unwrap());
+
+        let sets = [a, b.clone(), c];
+        assert_eq!(storage.sets_len(&sets).unwrap(), vec![1, 1, 0]);
+        let count = storage
+            .sets_add_and_count_containing(&sets[1..], &sets, 1)
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(storage.sets_len(&sets).unwrap(), vec![1, 2, 1]);
+
+        storage.set_remove(&b, 1).unwrap();
+        storage.set_remove(&PlayerSet::completed("d"), 1).unwrap();
+        assert!(!storage.set_contains(&b, 1).unwrap());
+        assert!(storage.set_contains(&b, 2).unwrap());
+    }
+
+    #[test]
+    fn test_stages() {
+        let storage = MemoryStorage::new();
+        assert_eq!(storage.stage("a", 1).unwrap(), 0);
+        storage.advance_stage("a", 1).unwrap();
+        storage.advance_stage("a", 1).unwrap();
+        assert_eq!(storage.stage("a", 1).unwrap(), 2);
+        assert_eq!(storage.stage("b", 1).unwrap(), 0);
+        storage.set_stage("a", 1, 5).unwrap();
+        storage.set_stage("a", 2, 5).unwrap();
+        storage.advance_stage("a", 1).unwrap();
+        assert_eq!(storage.stage("a", 1).unwrap(), 6);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::storage::{PlayerSet, StorageBackend, StorageResult};
+use crate::{BotError, BotResult};
+use redis::Commands;
+use std::{ops::DerefMut, sync::Mutex};
+
+pub struct RedisStorage {
+    redis: Mutex<redis::Connection>,
+}
+
+impl RedisStorage {
+    pub fn new(redis_url: &str) -> BotResult<Self> {
+        let redis = redis::Client::open(redis_url)
+            .and_then(|c| c.get_connection())
+            .map_err(|e| format!("Redis: {}", e))?;
+
+        Ok(Self {
+            redis: Mutex::new(redis),
+        })
+    }
+}
+
+/// The key names predate the other backends and are kept so that stored progress carries over.
+/// Check buckets are named after the stone challenge, where each check was a letter.
+fn set_key(set: &PlayerSet) -> String {
+    match set {
+        PlayerSet::Completed { challenge } => [challenge, "_completed_by"].concat(),
+        PlayerSet::CheckBucket { challenge, check } => [challenge, "_letter_", check].concat(),
+    }
+}
+
+/// A hash of stage indexes by player
+fn stages_key(challenge: &str) -> String {
+    [challenge, "_stage"].concat()
+}
+
+/// Connection failures are fatal since the bot has no way to make progress without the storage
+fn storage_error(message: String, error: redis::RedisError) -> BotError {
+    let fatal = error.is_io_error() || error.is_connection_refusal();
+    BotError::storage(message, error, fatal)
+}
+
+impl StorageBackend for RedisStorage {
+    fn get(&self, key: &str) -> StorageResult<Option<String>> {
+        let mut conn = self.redis.lock()?;
+        conn.get(key)
+            .map_err(|e| storage_error(format!("Cannot get {}", key), e))
+    }
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        let mut conn = self.redis.lock()?;
+        conn.set(key, value)
+            .map_err(|e| storage_error(format!("Cannot set {} to {}", key, value), e))
+    }
+
+    fn set_add(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let mut conn = self.redis.lock()?;
+        conn.sadd(set_key(set), player)
+            .map_err(|e| storage_error(format!("Cannot add {} to {}", player, set), e))
+    }
+
+    fn set_contains(&self, set: &PlayerSet, player: i64) -> StorageResult<bool> {
+        let mut conn = self.redis.lock()?;
+        conn.sismember(set_key(set), player).map_err(|e| {
+            storage_error(
+                format!("Cannot check membership of {} in {}", player, set),
+                e,
+            )
+        })
+    }
+
+    fn set_remove(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let mut conn = self.redis.lock()?;
+        conn.srem(set_key(set), player)
+            .map_err(|e| storage_error(format!("Cannot remove {} from {}", player, set), e))
+    }
+
+    fn sets_add_and_count_containing(
+        &self,
+        add_to_sets: &[PlayerSet],
+        count_in_sets: &[PlayerSet],
+        player: i64,
+    ) -> StorageResult<usize> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for set in add_to_sets {
+            pipe.sadd(set_key(set), player).ignore();
+        }
+        for set in count_in_sets {
+            pipe.sismember(set_key(set), player);
+        }
+        let mut conn = self.redis.lock()?;
+        pipe.query::<Vec<bool>>(conn.deref_mut())
+            .map(|r| r.iter().filter(|ismem| **ismem).count())
+            .map_err(|e| {
+                let message = format!(
+                    "Cannot add {} to {} sets with membership check across {} sets",
+                    player,
+                    add_to_sets.len(),
+                    count_in_sets.len()
+                );
+                storage_error(message, e)
+            })
+    }
+
+    fn sets_len(&self, sets: &[PlayerSet]) -> StorageResult<Vec<u64>> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for set in sets {
+            pipe.scard(set_key(set));
+        }
+        let mut conn = self.redis.lock()?;
+        pipe.query::<Vec<u64>>(conn.deref_mut())
+            .map_err(|e| storage_error("Cannot lookup set cardinality".into(), e))
+    }
+
+    fn stage(&self, challenge: &str, player: i64) -> StorageResult<usize> {
+        let mut conn = self.redis.lock()?;
+        conn.hget::<_, _, Option<usize>>(stages_key(challenge), player)
+            .map(|stage| stage.unwrap_or(0))
+            .map_err(|e| storage_error(format!("Cannot get {} stage of {}", challenge, player), e))
+    }
+
+    fn set_stage(&self, challenge: &str, player: i64, stage: usize) -> StorageResult<()> {
+        let mut conn = self.redis.lock()?;
+        conn.hset(stages_key(challenge), player, stage)
+            .map_err(|e| {
+                storage_error(
+                    format!("Cannot set {} stage of {} to {}", challenge, player, stage),
+                    e,
+                )
+            })
+    }
+
+    fn advance_stage(&self, challenge: &str, player: i64) -> StorageResult<()> {
+        let mut conn = self.redis.lock()?;
+        conn.hincr(stages_key(challenge), player, 1).map_err(|e| {
+            storage_error(
+                format!("Cannot advance {} stage of {}", challenge, player),
+                e,
+            )
+        })
+    }
+}
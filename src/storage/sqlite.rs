@@ -0,0 +1,328 @@
+use crate::storage::{PlayerSet, StorageBackend, StorageResult};
+use crate::{BotError, BotResult};
+use rusqlite::{params, Connection, ErrorCode, OptionalExtension};
+use std::sync::Mutex;
+
+/// Schema migrations, applied in order. The index of the last applied migration (plus one)
+/// is tracked in `PRAGMA user_version`; never edit a migration once it has been released,
+/// append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: challenge completions, check buckets and stage progress
+    r#"
+    CREATE TABLE completions (
+        challenge TEXT NOT NULL,
+        player INTEGER NOT NULL,
+        added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        PRIMARY KEY (challenge, player)
+    );
+    CREATE TABLE bucket_members (
+        challenge TEXT NOT NULL,
+        check_id TEXT NOT NULL,
+        player INTEGER NOT NULL,
+        added_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+        PRIMARY KEY (challenge, check_id, player)
+    );
+    CREATE TABLE stages (
+        challenge TEXT NOT NULL,
+        player INTEGER NOT NULL,
+        stage INTEGER NOT NULL,
+        PRIMARY KEY (challenge, player)
+    );
+    "#,
+    // 2: plain string values, such as the long poll checkpoint
+    r#"
+    CREATE TABLE string_values (
+        name TEXT PRIMARY KEY NOT NULL,
+        value TEXT NOT NULL
+    );
+    "#,
+];
+
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn new(path: &str) -> BotResult<Self> {
+        let mut conn = Connection::open(path).map_err(|e| format!("SQLite: {}", e))?;
+        migrate(&mut conn).map_err(|e| format!("SQLite migration failed: {}", e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let version: usize = conn.query_row("PRAGMA user_version", params![], |r| {
+        r.get::<_, i64>(0).map(|v| v as usize)
+    })?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "the database is at schema version {}, but this build only knows about {}",
+            version,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        // PRAGMA does not accept bound parameters
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+        println!("SQLite: applied schema migration {}", i + 1);
+    }
+    Ok(())
+}
+
+fn set_add(conn: &Connection, set: &PlayerSet, player: i64) -> rusqlite::Result<()> {
+    match set {
+        PlayerSet::Completed { challenge } => conn.execute(
+            "INSERT OR IGNORE INTO completions (challenge, player) VALUES (?1, ?2)",
+            params![challenge, player],
+        ),
+        PlayerSet::CheckBucket { challenge, check } => conn.execute(
+            "INSERT OR IGNORE INTO bucket_members (challenge, check_id, player)
+             VALUES (?1, ?2, ?3)",
+            params![challenge, check, player],
+        ),
+    }
+    .map(|_| ())
+}
+
+fn set_contains(conn: &Connection, set: &PlayerSet, player: i64) -> rusqlite::Result<bool> {
+    match set {
+        PlayerSet::Completed { challenge } => conn.query_row(
+            "SELECT 1 FROM completions WHERE challenge = ?1 AND player = ?2",
+            params![challenge, player],
+            |_| Ok(()),
+        ),
+        PlayerSet::CheckBucket { challenge, check } => conn.query_row(
+            "SELECT 1 FROM bucket_members WHERE challenge = ?1 AND check_id = ?2 AND player = ?3",
+            params![challenge, check, player],
+            |_| Ok(()),
+        ),
+    }
+    .optional()
+    .map(|r| r.is_some())
+}
+
+/// Errors caused by the database file itself are fatal, the rest only affect a single query
+fn storage_error(message: String, error: rusqlite::Error) -> BotError {
+    let fatal = match error {
+        rusqlite::Error::SqliteFailure(ref e, _) => match e.code {
+            ErrorCode::CannotOpen
+            | ErrorCode::DatabaseCorrupt
+            | ErrorCode::NotADatabase
+            | ErrorCode::DiskFull
+            | ErrorCode::ReadOnly
+            | ErrorCode::PermissionDenied
+            | ErrorCode::SystemIOFailure => true,
+            _ => false,
+        },
+        _ => false,
+    };
+    BotError::storage(message, error, fatal)
+}
+
+impl StorageBackend for SqliteStorage {
+    fn get(&self, key: &str) -> StorageResult<Option<String>> {
+        let conn = self.conn.lock()?;
+        conn.query_row(
+            "SELECT value FROM string_values WHERE name = ?1",
+            params![key],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| storage_error(format!("Cannot get {}", key), e))
+    }
+
+    fn set(&self, key: &str, value: &str) -> StorageResult<()> {
+        let conn = self.conn.lock()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO string_values (name, value) VALUES (?1, ?2)",
+            params![key, value],
+        )
+        .map(|_| ())
+        .map_err(|e| storage_error(format!("Cannot set {} to {}", key, value), e))
+    }
+
+    fn set_add(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let conn = self.conn.lock()?;
+        set_add(&conn, set, player)
+            .map_err(|e| storage_error(format!("Cannot add {} to {}", player, set), e))
+    }
+
+    fn set_contains(&self, set: &PlayerSet, player: i64) -> StorageResult<bool> {
+        let conn = self.conn.lock()?;
+        set_contains(&conn, set, player).map_err(|e| {
+            storage_error(
+                format!("Cannot check membership of {} in {}", player, set),
+                e,
+            )
+        })
+    }
+
+    fn set_remove(&self, set: &PlayerSet, player: i64) -> StorageResult<()> {
+        let conn = self.conn.lock()?;
+        match set {
+            PlayerSet::Completed { challenge } => conn.execute(
+                "DELETE FROM completions WHERE challenge = ?1 AND player = ?2",
+                params![challenge, player],
+            ),
+            PlayerSet::CheckBucket { challenge, check } => conn.execute(
+                "DELETE FROM bucket_members WHERE challenge = ?1 AND check_id = ?2 AND player = ?3",
+                params![challenge, check, player],
+            ),
+        }
+        .map(|_| ())
+        .map_err(|e| storage_error(format!("Cannot remove {} from {}", player, set), e))
+    }
+
+    fn sets_add_and_count_containing(
+        &self,
+        add_to_sets: &[PlayerSet],
+        count_in_sets: &[PlayerSet],
+        player: i64,
+    ) -> StorageResult<usize> {
+        let mut conn = self.conn.lock()?;
+        let result = conn.transaction().and_then(|tx| {
+            for set in add_to_sets {
+                set_add(&tx, set, player)?;
+            }
+            let mut count = 0;
+            for set in count_in_sets {
+                if set_contains(&tx, set, player)? {
+                    count += 1;
+                }
+            }
+            tx.commit().map(|_| count)
+        });
+        result.map_err(|e| {
+            let message = format!(
+                "Cannot add {} to {} sets with membership check across {} sets",
+                player,
+                add_to_sets.len(),
+                count_in_sets.len()
+            );
+            storage_error(message, e)
+        })
+    }
+
+    fn sets_len(&self, sets: &[PlayerSet]) -> StorageResult<Vec<u64>> {
+        let conn = self.conn.lock()?;
+        sets.iter()
+            .map(|set| match set {
+                PlayerSet::Completed { challenge } => conn.query_row(
+                    "SELECT COUNT(*) FROM completions WHERE challenge = ?1",
+                    params![challenge],
+                    |r| r.get::<_, i64>(0),
+                ),
+                PlayerSet::CheckBucket { challenge, check } => conn.query_row(
+                    "SELECT COUNT(*) FROM bucket_members WHERE challenge = ?1 AND check_id = ?2",
+                    params![challenge, check],
+                    |r| r.get::<_, i64>(0),
+                ),
+            })
+            .map(|count| count.map(|c| c as u64))
+            .collect::<rusqlite::Result<Vec<u64>>>()
+            .map_err(|e| storage_error("Cannot lookup set cardinality".into(), e))
+    }
+
+    fn stage(&self, challenge: &str, player: i64) -> StorageResult<usize> {
+        let conn = self.conn.lock()?;
+        conn.query_row(
+            "SELECT stage FROM stages WHERE challenge = ?1 AND player = ?2",
+            params![challenge, player],
+            |r| r.get::<_, i64>(0),
+        )
+        .optional()
+        .map(|stage| stage.unwrap_or(0) as usize)
+        .map_err(|e| storage_error(format!("Cannot get {} stage of {}", challenge, player), e))
+    }
+
+    fn set_stage(&self, challenge: &str, player: i64, stage: usize) -> StorageResult<()> {
+        let conn = self.conn.lock()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO stages (challenge, player, stage) VALUES (?1, ?2, ?3)",
+            params![challenge, player, stage as i64],
+        )
+        .map(|_| ())
+        .map_err(|e| {
+            storage_error(
+                format!("Cannot set {} stage of {} to {}", challenge, player, stage),
+                e,
+            )
+        })
+    }
+
+    fn advance_stage(&self, challenge: &str, player: i64) -> StorageResult<()> {
+        let conn = self.conn.lock()?;
+        conn.execute(
+            "INSERT INTO stages (challenge, player, stage) VALUES (?1, ?2, 1)
+             ON CONFLICT (challenge, player) DO UPDATE SET stage = stage + 1",
+            params![challenge, player],
+        )
+        .map(|_| ())
+        .map_err(|e| {
+            storage_error(
+                format!("Cannot advance {} stage of {}", challenge, player),
+                e,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations() {
+        let storage = SqliteStorage::new(":memory:").unwrap();
+        let mut conn = storage.conn.lock().unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", params![], |r| r.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        // reapplying is a no-op
+        migrate(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn test_sets_and_stages() {
+        let storage = SqliteStorage::new(":memory:").unwrap();
+        let a = PlayerSet::completed("a");
+        let b = PlayerSet::check_bucket("a", "b");
+        let c = PlayerSet::check_bucket("a", "c");
+        storage.set_add(&a, 1).unwrap();
+        storage.set_add(&a, 1).unwrap();
+        assert!(storage.set_contains(&a, 1).unwrap());
+        assert!(!storage.set_contains(&b, 1).unwrap());
+        assert!(!storage.set_contains(&PlayerSet::completed("b"), 1).unwrap());
+
+        let sets = [a, b.clone(), c];
+        let count = storage
+            .sets_add_and_count_containing(&sets[1..2], &sets, 1)
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(storage.sets_len(&sets).unwrap(), vec![1, 1, 0]);
+        storage.set_remove(&b, 1).unwrap();
+        assert!(!storage.set_contains(&b, 1).unwrap());
+
+        assert_eq!(storage.stage("a", 1).unwrap(), 0);
+        storage.advance_stage("a", 1).unwrap();
+        storage.advance_stage("a", 1).unwrap();
+        assert_eq!(storage.stage("a", 1).unwrap(), 2);
+        storage.set_stage("a", 1, 5).unwrap();
+        storage.set_stage("a", 2, 5).unwrap();
+        storage.advance_stage("a", 1).unwrap();
+        assert_eq!(storage.stage("a", 1).unwrap(), 6);
+        assert_eq!(storage.stage("b", 1).unwrap(), 0);
+
+        assert_eq!(storage.get("k").unwrap(), None);
+        storage.set("k", "1").unwrap();
+        storage.set("k", "2").unwrap();
+        assert_eq!(storage.get("k").unwrap(), Some("2".into()));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::BotResult;
+use std::collections::BTreeMap;
+
+/// A reply with `{variable}` placeholders. Numbers can be followed by the noun they count
+/// in the three Russian plural forms: `{passed|заклинание|заклинания|заклинаний}`.
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Variable {
+        name: String,
+        /// Forms for 1, 2 and 5 of something
+        plural: Option<Vec<String>>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateValue {
+    Number(usize),
+    Text(String),
+}
+
+pub type TemplateVars<'a> = BTreeMap<&'a str, TemplateValue>;
+
+impl Template {
+    pub fn parse(source: &str) -> BotResult<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            let rest = chars.as_str();
+            match c {
+                '{' | '}' if rest.starts_with(c) => {
+                    chars.next();
+                    text.push(c);
+                }
+                '{' => {
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed {{ in \"{}\"", source))?;
+                    let mut fields = rest[..end].split('|').map(|f| f.trim().to_owned());
+                    let name = fields.next().unwrap_or_default();
+                    let forms = fields.collect::<Vec<_>>();
+                    if name.is_empty() {
+                        return Err(format!("Empty variable name in \"{}\"", source).into());
+                    }
+                    let plural = match forms.len() {
+                        0 => None,
+                        3 => Some(forms),
+                        _ => {
+                            return Err(format!(
+                            "{{{}}} in \"{}\" needs three plural forms (1, 2 and 5 of something)",
+                            name, source
+                        )
+                            .into())
+                        }
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Variable { name, plural });
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err(format!("Unmatched }} in \"{}\"", source).into()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Variable { name, .. } => Some(name.as_str()),
+            Part::Text(_) => None,
+        })
+    }
+
+    /// Variables missing from `vars` are left as is
+    pub fn render(&self, vars: &TemplateVars) -> String {
+        let mut rendered = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable { name, plural } => match (vars.get(name.as_str()), plural) {
+                    (Some(TemplateValue::Number(n)), Some(forms)) => {
+                        rendered.push_str(&format!("{} {}", n, forms[plural_form(*n)]))
+                    }
+                    (Some(TemplateValue::Number(n)), None) => rendered.push_str(&n.to_string()),
+                    (Some(TemplateValue::Text(text)), _) => rendered.push_str(text),
+                    (None, _) => rendered.push_str(&format!("{{{}}}", name)),
+                },
+            }
+        }
+        rendered
+    }
+}
+
+/// The index of the Russian plural form for `n`: 1 заклинание, 2 заклинания, 5 заклинаний
+fn plural_form(n: usize) -> usize {
+    match (n % 10, n % 100) {
+        (1, rem) if rem != 11 => 0,
+        (2..=4, rem) if !(12..=14).contains(&rem) => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse(
+            "{name}, осталось {remaining|заклинание|заклинания|заклинаний} ({passed}/{required}) {{}}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.variables().collect::<Vec<_>>(),
+            vec!["name", "remaining", "passed", "required"]
+        );
+        let mut vars = TemplateVars::new();
+        vars.insert("name", TemplateValue::Text("Лосось".into()));
+        vars.insert("remaining", TemplateValue::Number(2));
+        vars.insert("passed", TemplateValue::Number(1));
+        assert_eq!(
+            template.render(&vars),
+            "Лосось, осталось 2 заклинания (1/{required}) {}"
+        );
+
+        assert!(Template::parse("{name").is_err());
+        assert!(Template::parse("name}").is_err());
+        assert!(Template::parse("{}").is_err());
+        assert!(Template::parse("{passed|этап|этапа}").is_err());
+    }
+
+    #[test]
+    fn test_plural_forms() {
+        let template = Template::parse("{n|заклинание|заклинания|заклинаний}").unwrap();
+        let render = |n| {
+            let mut vars = TemplateVars::new();
+            vars.insert("n", TemplateValue::Number(n));
+            template.render(&vars)
+        };
+        assert_eq!(render(1), "1 заклинание");
+        assert_eq!(render(2), "2 заклинания");
+        assert_eq!(render(5), "5 заклинаний");
+        assert_eq!(render(0), "0 заклинаний");
+        assert_eq!(render(11), "11 заклинаний");
+        assert_eq!(render(12), "12 заклинаний");
+        assert_eq!(render(21), "21 заклинание");
+        assert_eq!(render(24), "24 заклинания");
+        assert_eq!(render(111), "111 заклинаний");
+    }
+}
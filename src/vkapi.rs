@@ -1,16 +1,26 @@
+mod callback;
+mod error;
 mod http;
+mod keyboard;
 mod long_poll;
 mod messages;
 mod photos;
 mod types;
 mod users;
+pub use callback::{VkCallbackEndpoint, VkCallbackServer};
+pub use error::VkApiError;
 pub use http::Client;
+pub use keyboard::{VkButtonColor, VkEventAction, VkKeyboard};
 pub use long_poll::{VkLongPoll, VkLongPollState};
-pub use messages::VkMessagesApi;
+pub use messages::{VkMessagesApi, VkQuote};
 pub use photos::VkPhotosApi;
-pub use types::{VkMessage, VkPhoto};
+pub use types::{
+    VkAttachment, VkEvent, VkMessage, VkMessageEvent, VkPhoto, VkPhotoSize, GROUP_CHAT_PEER_OFFSET,
+};
 pub use users::{VkUser, VkUsersApi};
 
+use crate::BotError;
+
 pub struct VkApi<C: Client> {
     pub client: C,
     token: String,
@@ -28,6 +38,9 @@ impl<C: Client> std::fmt::Display for VkApi<C> {
     }
 }
 
+const API_MAX_RETRIES: usize = 3;
+const API_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(350);
+
 #[inline]
 fn api_request<'a>(
     method: &str,
@@ -72,6 +85,11 @@ impl<C: Client> VkApi<C> {
         })
     }
 
+    pub fn community_id(&self) -> &str {
+        &self.community_id
+    }
+
+    /// Transient API errors (rate limiting, flood control, internal errors) are retried with backoff
     fn call_api<T: serde::de::DeserializeOwned>(
         &self,
         method: &str,
@@ -79,6 +97,32 @@ impl<C: Client> VkApi<C> {
         json_response_key: Option<&str>,
     ) -> crate::BotResult<T> {
         let (url, api_query) = api_request(method, query, &self.token);
-        self.client.get_json(&url, &api_query, json_response_key)
+        let mut retries = 0;
+        loop {
+            match self.client.get_json(&url, &api_query, json_response_key) {
+                Err(BotError::VkApi(ref e)) if e.is_transient() && retries < API_MAX_RETRIES => {
+                    retries += 1;
+                    let delay = API_RETRY_DELAY * 2u32.pow(retries as u32 - 1);
+                    eprintln!(
+                        "{} failed with {}, retrying in {:?} ({}/{})",
+                        method, e, delay, retries, API_MAX_RETRIES
+                    );
+                    std::thread::sleep(delay);
+                }
+                result => break result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl VkApi<http::TestClient> {
+    pub fn with_fixture(fixture: &str) -> Self {
+        Self {
+            client: http::TestClient::new(fixture),
+            token: "token".into(),
+            community_name: "sample_community".into(),
+            community_id: "1001".into(),
+        }
     }
 }
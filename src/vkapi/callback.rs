@@ -0,0 +1,300 @@
+use crate::vkapi::long_poll::try_parse_update;
+use crate::vkapi::VkEvent;
+use crate::{BotError, BotResult};
+use serde_json::Value as JsonValue;
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+
+/// Callback API requests larger than this are rejected without being parsed
+const MAX_BODY_SIZE: u64 = 1024 * 1024;
+/// How many of the latest event ids are remembered to recognize events resent by VK
+const RECENT_EVENT_IDS: usize = 1024;
+
+/// A community that receives events through the callback server
+/// (*settings* -> *api usage* -> *callback api* in the community management panel)
+#[derive(Debug)]
+pub struct VkCallbackEndpoint {
+    pub community_id: String,
+    /// The string VK expects in response to the `confirmation` event
+    pub confirmation: String,
+    /// The secret key sent along with every event, if configured
+    pub secret: Option<String>,
+}
+
+/// An HTTP server accepting VK Callback API events for one or more communities
+pub struct VkCallbackServer {
+    server: tiny_http::Server,
+}
+
+impl VkCallbackServer {
+    pub fn bind(addr: &str) -> BotResult<Self> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|e| format!("Cannot start the callback server on {}: {}", addr, e))?;
+        Ok(Self { server })
+    }
+
+    pub fn local_addr(&self) -> std::net::SocketAddr {
+        self.server.server_addr()
+    }
+
+    /// Accepts requests until the listening socket fails, passing events
+    /// to `callback` along with the id of the community they were sent to.
+    /// VK is answered before `callback` is called, so that a callback blocked on a busy
+    /// worker does not time the request out; events VK resends anyway are passed on once.
+    pub fn serve<F>(&self, endpoints: &[VkCallbackEndpoint], mut callback: F) -> BotResult<()>
+    where
+        F: FnMut(&str, VkEvent),
+    {
+        let mut recent = RecentEvents::default();
+        loop {
+            let mut request = self
+                .server
+                .recv()
+                .map_err(|e| BotError::Network(format!("Callback server: {}", e)))?;
+
+            let (status, reply, event) = if request.method() != &tiny_http::Method::Post {
+                (405, String::from("method not allowed"), None)
+            } else {
+                let mut body = Vec::new();
+                match request
+                    .as_reader()
+                    .take(MAX_BODY_SIZE)
+                    .read_to_end(&mut body)
+                {
+                    Ok(_) => handle_event(endpoints, &body, &mut recent),
+                    Err(e) => (400, e.to_string(), None),
+                }
+            };
+            let response = tiny_http::Response::from_string(reply).with_status_code(status);
+            if let Err(e) = request.respond(response) {
+                eprintln!("Callback server: unable to respond: {}", e);
+            }
+            if let Some((community_id, event)) = event {
+                callback(community_id, event);
+            }
+        }
+    }
+}
+
+/// Ids of the events received most recently, oldest first
+#[derive(Default)]
+struct RecentEvents {
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentEvents {
+    /// Returns false if the id has already been seen
+    fn insert(&mut self, event_id: &str) -> bool {
+        if !self.ids.insert(event_id.to_owned()) {
+            return false;
+        }
+        self.order.push_back(event_id.to_owned());
+        if self.order.len() > RECENT_EVENT_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// The status and body to respond with, and the event to pass on along with its community id
+type HandledEvent<'a> = (u16, String, Option<(&'a str, VkEvent)>);
+
+fn handle_event<'a>(
+    endpoints: &'a [VkCallbackEndpoint],
+    body: &[u8],
+    recent: &mut RecentEvents,
+) -> HandledEvent<'a> {
+    let mut event: JsonValue = match serde_json::from_slice(body) {
+        Ok(event) => event,
+        Err(e) => return (400, format!("invalid json: {}", e), None),
+    };
+    let group_id = match event.get("group_id").and_then(|id| id.as_i64()) {
+        Some(id) => id.to_string(),
+        None => return (400, String::from("missing group_id"), None),
+    };
+    let endpoint = match endpoints.iter().find(|e| e.community_id == group_id) {
+        Some(endpoint) => endpoint,
+        None => return (404, String::from("unknown community"), None),
+    };
+    if let Some(ref secret) = endpoint.secret {
+        if event.get("secret").and_then(|s| s.as_str()) != Some(secret) {
+            eprintln!(
+                "Callback server: invalid secret in a request for {}",
+                group_id
+            );
+            return (403, String::from("invalid secret"), None);
+        }
+    }
+    match event.get("type").and_then(|t| t.as_str()) {
+        Some("confirmation") => (200, endpoint.confirmation.clone(), None),
+        // VK keeps resending events until it gets an "ok", even the ones we are not interested in
+        _ => {
+            let resent = match event.get("event_id").and_then(|id| id.as_str()) {
+                Some(event_id) => !recent.insert(event_id),
+                None => false,
+            };
+            let parsed = if resent {
+                None
+            } else {
+                try_parse_update(&mut event).map(|e| (endpoint.community_id.as_str(), e))
+            };
+            (200, String::from("ok"), parsed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vkapi::VkMessage;
+    use std::io::Write;
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn start_server() -> (SocketAddr, Receiver<(String, VkEvent)>) {
+        let server = VkCallbackServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            let endpoints = [VkCallbackEndpoint {
+                community_id: "1001".into(),
+                confirmation: "confirm-me".into(),
+                secret: Some("s3cret".into()),
+            }];
+            server
+                .serve(&endpoints, |community, event| {
+                    tx.send((community.to_owned(), event)).unwrap()
+                })
+                .unwrap();
+        });
+        (addr, rx)
+    }
+
+    fn post(addr: SocketAddr, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body_start = response.find("\r\n\r\n").unwrap() + 4;
+        let body = response[body_start..].to_owned();
+        (status, body)
+    }
+
+    #[test]
+    fn test_confirmation() {
+        let (addr, _) = start_server();
+        let (status, body) = post(
+            addr,
+            r#"{"type": "confirmation", "group_id": 1001, "secret": "s3cret"}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body, "confirm-me");
+    }
+
+    #[test]
+    fn test_rejects_invalid_requests() {
+        let (addr, _) = start_server();
+        let (status, _) = post(
+            addr,
+            r#"{"type": "confirmation", "group_id": 1001, "secret": "guess"}"#,
+        );
+        assert_eq!(status, 403);
+        let (status, _) = post(
+            addr,
+            r#"{"type": "confirmation", "group_id": 2002, "secret": "s3cret"}"#,
+        );
+        assert_eq!(status, 404);
+        let (status, _) = post(addr, "not json");
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn test_message_new() {
+        let (addr, rx) = start_server();
+        let (status, body) = post(
+            addr,
+            r#"{
+                "type": "message_new",
+                "group_id": 1001,
+                "event_id": "cafedead",
+                "secret": "s3cret",
+                "object": {
+                    "message": {"from_id": 1010, "peer_id": 1010, "text": "hello", "attachments": []},
+                    "client_info": {}
+                }
+            }"#,
+        );
+        assert_eq!((status, body.as_str()), (200, "ok"));
+        let (community, event) = rx.recv().unwrap();
+        assert_eq!(community, "1001");
+        assert_eq!(
+            event,
+            VkEvent::MessageNew(VkMessage {
+                text: "hello".into(),
+                from_id: 1010,
+                peer_id: 1010,
+                id: 0,
+                conversation_message_id: 0,
+                date: 0,
+                attachments: vec![],
+                forwarded: vec![],
+                reply_to: None,
+                payload: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_drops_resent_events() {
+        let (addr, rx) = start_server();
+        let event = |event_id: &str, text: &str| {
+            format!(
+                r#"{{
+                    "type": "message_new",
+                    "group_id": 1001,
+                    "event_id": "{}",
+                    "secret": "s3cret",
+                    "object": {{
+                        "message": {{"from_id": 1010, "peer_id": 1010, "text": "{}", "attachments": []}},
+                        "client_info": {{}}
+                    }}
+                }}"#,
+                event_id, text
+            )
+        };
+        assert_eq!(post(addr, &event("cafedead", "hello")), (200, "ok".into()));
+        assert_eq!(post(addr, &event("cafedead", "hello")), (200, "ok".into()));
+        assert_eq!(post(addr, &event("deadbeef", "bye")), (200, "ok".into()));
+        let texts = (0..2)
+            .map(|_| match rx.recv().unwrap() {
+                (_, VkEvent::MessageNew(msg)) => msg.text,
+                (_, event) => panic!("Unexpected event {:?}", event),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(texts, vec!["hello", "bye"]);
+    }
+
+    #[test]
+    fn test_recent_events_are_bounded() {
+        let mut recent = RecentEvents::default();
+        assert!(recent.insert("0"));
+        assert!(!recent.insert("0"));
+        for i in 1..=RECENT_EVENT_IDS {
+            assert!(recent.insert(&i.to_string()));
+        }
+        assert_eq!(recent.order.len(), RECENT_EVENT_IDS);
+        // the oldest id has been forgotten
+        assert!(recent.insert("0"));
+    }
+}
@@ -0,0 +1,57 @@
+use serde_derive::Deserialize;
+
+/// VK API error codes, see https://vk.com/dev/errors
+pub const VK_AUTH_FAILED: i64 = 5;
+pub const VK_TOO_MANY_REQUESTS: i64 = 6;
+pub const VK_FLOOD_CONTROL: i64 = 9;
+pub const VK_INTERNAL_ERROR: i64 = 10;
+pub const VK_INVALID_USER_ID: i64 = 113;
+
+/// The `error` object returned by VK API methods in place of `response`
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct VkApiError {
+    #[serde(rename = "error_code")]
+    pub code: i64,
+    #[serde(rename = "error_msg")]
+    pub message: String,
+    #[serde(default)]
+    pub request_params: Vec<VkRequestParam>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct VkRequestParam {
+    pub key: String,
+    pub value: String,
+}
+
+impl VkApiError {
+    /// Errors that go away by themselves if the request is repeated later
+    pub fn is_transient(&self) -> bool {
+        match self.code {
+            VK_TOO_MANY_REQUESTS | VK_FLOOD_CONTROL | VK_INTERNAL_ERROR => true,
+            _ => false,
+        }
+    }
+
+    /// The access token is invalid or has been revoked
+    pub fn is_auth(&self) -> bool {
+        self.code == VK_AUTH_FAILED
+    }
+}
+
+impl std::fmt::Display for VkApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VK API error {}: {}", self.code, self.message)?;
+        let params = self
+            .request_params
+            .iter()
+            // the token is echoed back in some responses, keep it out of the logs
+            .filter(|p| p.key != "access_token")
+            .map(|p| format!("{}={}", p.key, p.value))
+            .collect::<Vec<_>>();
+        if !params.is_empty() {
+            write!(f, " (request: {})", params.join(", "))?;
+        }
+        Ok(())
+    }
+}
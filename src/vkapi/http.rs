@@ -1,3 +1,6 @@
+use crate::vkapi::VkApiError;
+use crate::BotError;
+
 pub trait Client: Send + Sync + 'static {
     fn fetch(
         &self,
@@ -71,6 +74,16 @@ impl Client for ureq::Agent {
             Some(data) => request.send_bytes(data),
             _ => request.call(),
         };
+        if let Some(err) = resp.synthetic_error() {
+            return Err(BotError::Network(format!("{}: {}", url, err)));
+        }
+        if resp.server_error() {
+            return Err(BotError::Network(format!(
+                "{}: HTTP {}",
+                url,
+                resp.status()
+            )));
+        }
         let mut data = Vec::new();
         resp.into_reader().read_to_end(&mut data)?;
 
@@ -91,31 +104,34 @@ fn extract_json<T: serde::de::DeserializeOwned>(
     body: &[u8],
     response_key: Option<&str>,
 ) -> crate::BotResult<T> {
+    let mut json =
+        serde_json::from_slice::<serde_json::Value>(body).map_err(|e| json_error(url, body, e))?;
+    if let Some(error) = json
+        .get_mut("error")
+        .filter(|e| e.get("error_code").is_some())
+    {
+        return match serde_json::from_value::<VkApiError>(error.take()) {
+            Ok(api_error) => Err(BotError::VkApi(api_error)),
+            Err(e) => Err(json_error(url, body, e)),
+        };
+    }
     if let Some(key) = response_key {
-        serde_json::from_slice::<serde_json::Value>(body)
-            .map_err(|e| json_error(url, body, e.into()))?
-            .get_mut(key)
-            .ok_or_else(|| json_error(url, body, format!("Missing response key {}", key).into()))
-            .and_then(|r| {
-                serde_json::from_value(r.take()).map_err(|e| json_error(url, body, e.into()))
-            })
+        json.get_mut(key)
+            .ok_or_else(|| json_error(url, body, format!("Missing response key {}", key)))
+            .and_then(|r| serde_json::from_value(r.take()).map_err(|e| json_error(url, body, e)))
     } else {
-        serde_json::from_slice(&body).map_err(|e| json_error(url, body, e.into()))
+        serde_json::from_value(json).map_err(|e| json_error(url, body, e))
     }
 }
 
-fn json_error(
-    url: &str,
-    source: &[u8],
-    error: Box<dyn std::error::Error>,
-) -> Box<dyn std::error::Error> {
-    format!(
+/// Not retried: VK responds to the same request with the same unexpected body
+fn json_error<E: std::fmt::Display>(url: &str, source: &[u8], error: E) -> BotError {
+    BotError::Other(format!(
         "Unable to deserialize response: {}\nRequest URL: {}\nResponse body: {}",
         error,
         url,
         std::str::from_utf8(source).unwrap_or("*invalid utf8*")
-    )
-    .into()
+    ))
 }
 
 #[cfg(test)]
@@ -140,18 +156,49 @@ pub mod test_client {
         url: String,
         query: HashMap<String, String>,
         headers: Option<HashMap<String, String>>,
+        #[serde(default)]
         response: serde_json::Value,
+        /// A fixture file served as is instead of `response`, such as a photo
+        response_file: Option<String>,
+    }
+
+    fn fixture_path(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
     }
 
     impl TestClient {
         pub fn new(fixture: &str) -> Self {
-            let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+            let path = fixture_path(fixture);
             let file = std::fs::File::open(&path).expect(&format!("Failed to open {}", path));
             let fixtures = Arc::new(serde_json::from_reader(file).unwrap());
             Self { fixtures }
         }
     }
 
+    /// Requests the code under test failed to make are as much of a failure as unexpected ones
+    impl Drop for TestClient {
+        fn drop(&mut self) {
+            if std::thread::panicking() {
+                return;
+            }
+            let fixtures = self.fixtures.lock().unwrap();
+            let remaining = fixtures.borrow();
+            assert!(
+                remaining.is_empty(),
+                "Expected requests were not made: {:?}",
+                remaining
+            );
+        }
+    }
+
+    /// A `"*"` value in the fixture matches any value of the parameter (e.g. `random_id`)
+    fn query_matches(expected: &HashMap<String, String>, actual: &HashMap<String, String>) -> bool {
+        expected.len() == actual.len()
+            && expected
+                .iter()
+                .all(|(k, v)| actual.get(k).map_or(false, |a| v == "*" || a == v))
+    }
+
     impl Client for TestClient {
         fn fetch(
             &self,
@@ -168,10 +215,13 @@ pub mod test_client {
             match expected {
                 Some(ref fixture)
                     if url == fixture.url
-                        && query_map == fixture.query
+                        && query_matches(&fixture.query, &query_map)
                         && &header_map == fixture.headers.as_ref().unwrap_or(&HashMap::new()) =>
                 {
-                    Ok(serde_json::to_vec(&fixture.response).unwrap())
+                    match fixture.response_file {
+                        Some(ref name) => Ok(std::fs::read(fixture_path(name)).unwrap()),
+                        None => Ok(serde_json::to_vec(&fixture.response).unwrap()),
+                    }
                 }
                 _ => Err(format!(
                     "Expected request: {:?}, got: {:?} {:?} {:?} {:?}",
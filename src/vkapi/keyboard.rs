@@ -0,0 +1,207 @@
+use serde_derive::Serialize;
+use serde_json::json;
+
+/// A keyboard sent along with a message, built row by row:
+/// `VkKeyboard::one_time().text("Да", "yes", VkButtonColor::Positive).row().link(...)`.
+/// Text buttons send their command in the message payload, see `VkMessage::command`;
+/// callback buttons send it in a `VkMessageEvent`.
+#[derive(Clone, Debug)]
+pub struct VkKeyboard {
+    one_time: bool,
+    inline: bool,
+    rows: Vec<Vec<VkButton>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VkButtonColor {
+    Primary,
+    Secondary,
+    Negative,
+    Positive,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct VkButton {
+    action: VkButtonAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<VkButtonColor>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum VkButtonAction {
+    /// Sends the label as a message
+    Text {
+        label: String,
+        payload: String,
+    },
+    /// Delivers a `message_event` to the community without sending a message
+    Callback {
+        label: String,
+        payload: String,
+    },
+    OpenLink {
+        label: String,
+        link: String,
+    },
+}
+
+/// What the client does in response to a callback button press
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VkEventAction {
+    /// Shows a notification that disappears after a few seconds, up to 90 characters long
+    ShowSnackbar {
+        text: String,
+    },
+    OpenLink {
+        link: String,
+    },
+}
+
+impl VkKeyboard {
+    /// Shown under the input field and hidden once a button is pressed
+    pub fn one_time() -> Self {
+        Self::new(true, false)
+    }
+
+    /// Attached to the message itself
+    pub fn inline() -> Self {
+        Self::new(false, true)
+    }
+
+    /// Hides the keyboard shown by an earlier message
+    pub fn empty() -> Self {
+        Self::new(true, false)
+    }
+
+    fn new(one_time: bool, inline: bool) -> Self {
+        Self {
+            one_time,
+            inline,
+            rows: vec![vec![]],
+        }
+    }
+
+    /// Starts a new row of buttons
+    pub fn row(mut self) -> Self {
+        self.rows.push(vec![]);
+        self
+    }
+
+    pub fn text(self, label: &str, command: &str, color: VkButtonColor) -> Self {
+        let action = VkButtonAction::Text {
+            label: label.to_owned(),
+            payload: payload(command),
+        };
+        self.button(action, Some(color))
+    }
+
+    pub fn callback(self, label: &str, command: &str, color: VkButtonColor) -> Self {
+        let action = VkButtonAction::Callback {
+            label: label.to_owned(),
+            payload: payload(command),
+        };
+        self.button(action, Some(color))
+    }
+
+    pub fn link(self, label: &str, url: &str) -> Self {
+        let action = VkButtonAction::OpenLink {
+            label: label.to_owned(),
+            link: url.to_owned(),
+        };
+        self.button(action, None)
+    }
+
+    fn button(mut self, action: VkButtonAction, color: Option<VkButtonColor>) -> Self {
+        self.rows
+            .last_mut()
+            .unwrap()
+            .push(VkButton { action, color });
+        self
+    }
+
+    /// The `keyboard` parameter of `messages.send`
+    pub fn to_json(&self) -> String {
+        let rows = self
+            .rows
+            .iter()
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>();
+        let mut keyboard = json!({ "inline": self.inline, "buttons": rows });
+        // VK rejects one_time for inline keyboards
+        if !self.inline {
+            keyboard["one_time"] = self.one_time.into();
+        }
+        keyboard.to_string()
+    }
+}
+
+impl VkEventAction {
+    /// The `event_data` parameter of `messages.sendMessageEventAnswer`
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+fn payload(command: &str) -> String {
+    json!({ "command": command }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_json() {
+        let keyboard = VkKeyboard::one_time()
+            .text("Да", "yes", VkButtonColor::Positive)
+            .text("Нет", "no", VkButtonColor::Negative)
+            .row()
+            .link("Сайт", "https://example.com")
+            .row();
+        let json: serde_json::Value = serde_json::from_str(&keyboard.to_json()).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "one_time": true,
+                "inline": false,
+                "buttons": [
+                    [
+                        {
+                            "action": { "type": "text", "label": "Да", "payload": "{\"command\":\"yes\"}" },
+                            "color": "positive"
+                        },
+                        {
+                            "action": { "type": "text", "label": "Нет", "payload": "{\"command\":\"no\"}" },
+                            "color": "negative"
+                        }
+                    ],
+                    [{ "action": { "type": "open_link", "label": "Сайт", "link": "https://example.com" } }]
+                ]
+            })
+        );
+
+        let inline = VkKeyboard::inline().callback("?", "hint", VkButtonColor::Secondary);
+        let json: serde_json::Value = serde_json::from_str(&inline.to_json()).unwrap();
+        assert_eq!(json["inline"], true);
+        assert!(json.get("one_time").is_none());
+        assert_eq!(json["buttons"][0][0]["action"]["type"], "callback");
+
+        let empty: serde_json::Value =
+            serde_json::from_str(&VkKeyboard::empty().to_json()).unwrap();
+        assert_eq!(
+            empty,
+            json!({ "one_time": true, "inline": false, "buttons": [] })
+        );
+
+        let snackbar = VkEventAction::ShowSnackbar {
+            text: "Готово".into(),
+        };
+        assert_eq!(
+            snackbar.to_json(),
+            r#"{"type":"show_snackbar","text":"Готово"}"#
+        );
+    }
+}
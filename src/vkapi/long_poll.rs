@@ -1,4 +1,6 @@
-use crate::vkapi::{Client, VkApi, VkMessage, VkPhoto};
+use crate::vkapi::{
+    Client, VkApi, VkAttachment, VkEvent, VkMessage, VkMessageEvent, VkPhoto, VkPhotoSize,
+};
 use serde_derive::Deserialize;
 use serde_json::Value as JsonValue;
 
@@ -28,9 +30,22 @@ impl<'a, C: Client> VkLongPoll<'a, C> {
         Ok(Self { state, api })
     }
 
+    /// Starts polling from a previously saved `ts`, so that messages received while the bot
+    /// was offline are delivered. If VK no longer has them, polling starts from the current state.
+    pub fn resume(api: &'a VkApi<C>, ts: String) -> crate::BotResult<VkLongPoll<'a, C>> {
+        let mut state = get_long_poll_state(api)?;
+        state.ts = ts;
+        Ok(Self { state, api })
+    }
+
+    /// The position of the next batch of updates, to be passed to `resume` after a restart
+    pub fn ts(&self) -> &str {
+        &self.state.ts
+    }
+
     pub fn poll_once<F>(&mut self, mut callback: F) -> crate::BotResult<()>
     where
-        F: FnMut(VkMessage) -> (),
+        F: FnMut(VkEvent) -> (),
     {
         let params = [
             ("act", "a_check"),
@@ -67,21 +82,52 @@ impl<'a, C: Client> VkLongPoll<'a, C> {
     }
 }
 
-fn try_parse_update(update: &mut JsonValue) -> Option<VkMessage> {
-    try_parse_message(update.get_mut("object")?.get_mut("message")?)
+pub(super) fn try_parse_update(update: &mut JsonValue) -> Option<VkEvent> {
+    let kind = update.get("type")?.as_str()?.to_owned();
+    let object = update.get_mut("object")?;
+    let user_id = object.get("user_id").and_then(|id| id.as_i64());
+    match kind.as_str() {
+        "message_new" => try_parse_message(object.get_mut("message")?).map(VkEvent::MessageNew),
+        // Unlike new messages, edited ones are not wrapped in "message"
+        "message_edit" => try_parse_message(object).map(VkEvent::MessageEdit),
+        "message_event" => Some(VkEvent::MessageEvent(VkMessageEvent {
+            event_id: take_string(object, "event_id"),
+            user_id: user_id?,
+            peer_id: object.get("peer_id")?.as_i64()?,
+            payload: object.get_mut("payload").and_then(try_parse_payload),
+        })),
+        "message_allow" => Some(VkEvent::MessageAllow { user_id: user_id? }),
+        "message_deny" => Some(VkEvent::MessageDeny { user_id: user_id? }),
+        "group_join" => Some(VkEvent::GroupJoin { user_id: user_id? }),
+        "group_leave" => Some(VkEvent::GroupLeave { user_id: user_id? }),
+        _ => None,
+    }
+}
+
+/// Messages carry button payloads as JSON-encoded strings, message events as objects
+fn try_parse_payload(payload: &mut JsonValue) -> Option<JsonValue> {
+    match payload.take() {
+        JsonValue::String(p) => serde_json::from_str(&p).ok(),
+        JsonValue::Null => None,
+        p => Some(p),
+    }
 }
 
 fn try_parse_message(message: &mut JsonValue) -> Option<VkMessage> {
-    let text = match message.get_mut("text").map(|t| t.take()) {
-        Some(JsonValue::String(s)) => s,
-        _ => String::new(),
-    };
+    let text = take_string(message, "text");
     let from_id = message.get("from_id")?.as_i64()?;
-    let attachments = message
+    let peer_id = message["peer_id"].as_i64().unwrap_or(0);
+    let id = message["id"].as_i64().unwrap_or(0);
+    let conversation_message_id = message["conversation_message_id"].as_i64().unwrap_or(0);
+    let date = message["date"].as_u64().unwrap_or(0);
+    let mut attachments: Vec<VkAttachment> = message
         .get_mut("attachments")
         .and_then(|a| a.as_array_mut())
-        .map(|a| a.iter_mut().flat_map(parse_attachments).collect())
+        .map(|a| a.iter_mut().filter_map(try_parse_attachment).collect())
         .unwrap_or_default();
+    if let Some(geo) = message.get_mut("geo").and_then(try_parse_geo) {
+        attachments.push(geo);
+    }
     let forwarded = message
         .get_mut("fwd_messages")
         .and_then(|a| a.as_array_mut())
@@ -91,50 +137,149 @@ fn try_parse_message(message: &mut JsonValue) -> Option<VkMessage> {
         .get_mut("reply_message")
         .and_then(try_parse_message)
         .map(Box::new);
+    let payload = message.get_mut("payload").and_then(try_parse_payload);
 
     Some(VkMessage {
         text,
         from_id,
+        peer_id,
+        id,
+        conversation_message_id,
+        date,
         attachments,
         forwarded,
         reply_to,
+        payload,
     })
 }
 
-fn parse_attachments(attachment: &mut JsonValue) -> Vec<VkPhoto> {
-    let wall = attachment
-        .get_mut("wall")
-        .and_then(|w| w.get_mut("attachments"))
-        .and_then(|a| a.as_array_mut());
-    if let Some(wall_atts) = wall {
-        return wall_atts.iter_mut().flat_map(parse_attachments).collect();
-    }
-    let doc_photo = attachment
-        .get_mut("doc")
-        .and_then(|d| d.get_mut("preview"))
-        .and_then(try_parse_photo);
-    if let Some(photo) = doc_photo.or_else(|| try_parse_photo(attachment)) {
-        vec![photo]
-    } else {
-        vec![]
+fn try_parse_attachment(attachment: &mut JsonValue) -> Option<VkAttachment> {
+    // The attachment object is stored under the key named by its type
+    let kind = attachment.get("type")?.as_str()?.to_owned();
+    let obj = attachment.get_mut(&kind)?;
+    match kind.as_str() {
+        "photo" => try_parse_photo(obj).map(VkAttachment::Photo),
+        "doc" => Some(VkAttachment::Doc {
+            title: take_string(obj, "title"),
+            ext: take_string(obj, "ext"),
+            url: take_string(obj, "url"),
+            preview: obj
+                .get_mut("preview")
+                .and_then(|p| p.get_mut("photo"))
+                .and_then(try_parse_photo),
+        }),
+        "audio_message" => Some(VkAttachment::AudioMessage {
+            duration: obj.get("duration")?.as_u64()?,
+            link_ogg: take_string(obj, "link_ogg"),
+            link_mp3: take_string(obj, "link_mp3"),
+        }),
+        "sticker" => Some(VkAttachment::Sticker {
+            id: obj.get("sticker_id")?.as_i64()?,
+            image: try_parse_sticker(obj),
+        }),
+        "graffiti" => try_parse_graffiti(obj).map(VkAttachment::Graffiti),
+        "link" => Some(VkAttachment::Link {
+            url: take_string(obj, "url"),
+            title: take_string(obj, "title"),
+        }),
+        "wall" => Some(VkAttachment::Wall {
+            // Older API versions only have "to_id"
+            owner_id: obj.get("owner_id").or_else(|| obj.get("to_id"))?.as_i64()?,
+            id: obj.get("id")?.as_i64()?,
+            text: take_string(obj, "text"),
+            attachments: obj
+                .get_mut("attachments")
+                .and_then(|a| a.as_array_mut())
+                .map(|a| a.iter_mut().filter_map(try_parse_attachment).collect())
+                .unwrap_or_default(),
+        }),
+        "video" => Some(VkAttachment::Video {
+            owner_id: obj.get("owner_id")?.as_i64()?,
+            id: obj.get("id")?.as_i64()?,
+            title: take_string(obj, "title"),
+            duration: obj["duration"].as_u64().unwrap_or(0),
+        }),
+        "poll" => Some(VkAttachment::Poll {
+            id: obj.get("id")?.as_i64()?,
+            question: take_string(obj, "question"),
+            answers: obj
+                .get_mut("answers")
+                .and_then(|a| a.as_array_mut())
+                .map(|a| a.iter_mut().map(|a| take_string(a, "text")).collect())
+                .unwrap_or_default(),
+        }),
+        _ => None,
     }
 }
 
-fn try_parse_photo(photo_obj: &mut JsonValue) -> Option<VkPhoto> {
-    let mut opt_size_obj = photo_obj
-        .get_mut("photo")?
+fn try_parse_geo(geo: &mut JsonValue) -> Option<VkAttachment> {
+    let coordinates = geo.get("coordinates")?;
+    let latitude = coordinates.get("latitude")?.as_f64()?;
+    let longitude = coordinates.get("longitude")?.as_f64()?;
+    let place = geo
+        .get_mut("place")
+        .map(|p| take_string(p, "title"))
+        .filter(|title| !title.is_empty());
+    Some(VkAttachment::Geo {
+        latitude,
+        longitude,
+        place,
+    })
+}
+
+fn try_parse_photo(photo: &mut JsonValue) -> Option<VkPhoto> {
+    // Other size types are cropped to a fixed aspect ratio
+    let sizes = photo
         .get_mut("sizes")?
         .as_array_mut()?
-        .drain(..)
+        .iter_mut()
         .filter(|size| ["m", "x", "y", "z", "w"].contains(&size["type"].as_str().unwrap_or("")))
-        .min_by_key(|size| size["width"].as_u64().unwrap_or(std::u64::MAX))?;
+        .filter_map(|size| {
+            // Document previews have "src" instead of "url"
+            let key = if size.get("url").is_some() {
+                "url"
+            } else {
+                "src"
+            };
+            let url = size.get_mut(key)?.take();
+            try_parse_size(size, url)
+        })
+        .collect();
+    VkPhoto::new(sizes)
+}
+
+fn try_parse_sticker(sticker: &mut JsonValue) -> Option<VkPhoto> {
+    let sizes = sticker
+        .get_mut("images")?
+        .as_array_mut()?
+        .iter_mut()
+        .filter_map(|image| {
+            let url = image.get_mut("url")?.take();
+            try_parse_size(image, url)
+        })
+        .collect();
+    VkPhoto::new(sizes)
+}
+
+fn try_parse_graffiti(graffiti: &mut JsonValue) -> Option<VkPhoto> {
+    let url = graffiti.get_mut("url")?.take();
+    VkPhoto::new(vec![try_parse_size(graffiti, url)?])
+}
 
-    if let Some(JsonValue::String(url)) = opt_size_obj.get_mut("url").map(|u| u.take()) {
-        Some(VkPhoto(url))
-    } else if let Some(JsonValue::String(url)) = opt_size_obj.get_mut("src").map(|u| u.take()) {
-        Some(VkPhoto(url))
-    } else {
-        None
+fn take_string(obj: &mut JsonValue, key: &str) -> String {
+    match obj.get_mut(key).map(|v| v.take()) {
+        Some(JsonValue::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+fn try_parse_size(size: &JsonValue, url: JsonValue) -> Option<VkPhotoSize> {
+    match url {
+        JsonValue::String(url) => Some(VkPhotoSize {
+            width: size["width"].as_u64().unwrap_or(0),
+            url,
+        }),
+        _ => None,
     }
 }
 
@@ -142,14 +287,20 @@ fn try_parse_photo(photo_obj: &mut JsonValue) -> Option<VkPhoto> {
 mod tests {
     use super::*;
 
+    fn photo(sizes: &[(u64, &str)]) -> VkPhoto {
+        let sizes = sizes
+            .iter()
+            .map(|(width, url)| VkPhotoSize {
+                width: *width,
+                url: url.to_string(),
+            })
+            .collect();
+        VkPhoto::new(sizes).unwrap()
+    }
+
     #[test]
     fn test_poll_instantiation() {
-        let vk = VkApi {
-            client: crate::vkapi::http::TestClient::new("long_poll_init.json"),
-            token: "token".into(),
-            community_name: "sample_community".into(),
-            community_id: "1001".into(),
-        };
+        let vk = VkApi::with_fixture("long_poll_init.json");
         let poll = VkLongPoll::init(&vk).unwrap();
         assert_eq!(poll.state.key, "long_poll_key");
         assert_eq!(poll.state.server, "https://long_poll_server");
@@ -158,12 +309,7 @@ mod tests {
 
     #[test]
     fn test_error_response() {
-        let vk = VkApi {
-            client: crate::vkapi::http::TestClient::new("long_poll_failed.json"),
-            token: "token".into(),
-            community_name: "sample_community".into(),
-            community_id: "1001".into(),
-        };
+        let vk = VkApi::with_fixture("long_poll_failed.json");
         let mut lp = VkLongPoll {
             api: &vk,
             state: VkLongPollState {
@@ -178,14 +324,19 @@ mod tests {
         assert_eq!(lp.state.ts, "101");
     }
 
+    #[test]
+    fn test_resume_out_of_range() {
+        let vk = VkApi::with_fixture("long_poll_resume.json");
+        let mut lp = VkLongPoll::resume(&vk, "42".into()).unwrap();
+        assert_eq!(lp.ts(), "42");
+        lp.poll_once(|_| {}).unwrap();
+        assert_eq!(lp.state.key, "new_long_poll_key");
+        assert_eq!(lp.ts(), "101");
+    }
+
     #[test]
     fn test_parse_reply_document() {
-        let vk = VkApi {
-            client: crate::vkapi::http::TestClient::new("long_poll_reply_document.json"),
-            token: "token".into(),
-            community_name: "sample_community".into(),
-            community_id: "1001".into(),
-        };
+        let vk = VkApi::with_fixture("long_poll_reply_document.json");
         let mut msg: Option<VkMessage> = None;
         VkLongPoll {
             api: &vk,
@@ -195,34 +346,53 @@ mod tests {
                 ts: "100".into(),
             },
         }
-        .poll_once(|m| msg = Some(m))
+        .poll_once(|event| {
+            if let VkEvent::MessageNew(m) = event {
+                msg = Some(m)
+            }
+        })
         .unwrap();
         assert_eq!(
             msg,
             Some(VkMessage {
                 text: "but they are!".to_owned(),
                 from_id: 1010,
+                peer_id: 1000,
+                id: 2,
+                conversation_message_id: 2,
+                date: 1580661416,
                 attachments: vec![],
                 forwarded: vec![],
                 reply_to: Some(Box::new(VkMessage {
                     text: "uh, docs aren't photos...".into(),
                     from_id: 1000,
-                    attachments: vec![VkPhoto("$med_url".into())],
+                    peer_id: 1010,
+                    id: 1,
+                    conversation_message_id: 1,
+                    date: 1580661372,
+                    attachments: vec![VkAttachment::Doc {
+                        title: "photo.png".into(),
+                        ext: "png".into(),
+                        url: "$full_url".into(),
+                        preview: Some(photo(&[
+                            (130, "$med_url"),
+                            (604, "$x_url"),
+                            (807, "$y_url"),
+                            (1280, "$z_url")
+                        ]))
+                    }],
                     forwarded: vec![],
-                    reply_to: None
-                }))
+                    reply_to: None,
+                    payload: None
+                })),
+                payload: None
             })
         );
     }
 
     #[test]
     fn test_parse_post() {
-        let vk = VkApi {
-            client: crate::vkapi::http::TestClient::new("long_poll_fwd_post.json"),
-            token: "token".into(),
-            community_name: "sample_community".into(),
-            community_id: "1001".into(),
-        };
+        let vk = VkApi::with_fixture("long_poll_fwd_post.json");
         let mut msg: Option<VkMessage> = None;
         VkLongPoll {
             api: &vk,
@@ -232,28 +402,42 @@ mod tests {
                 ts: "100".into(),
             },
         }
-        .poll_once(|m| msg = Some(m))
+        .poll_once(|event| {
+            if let VkEvent::MessageNew(m) = event {
+                msg = Some(m)
+            }
+        })
         .unwrap();
         assert_eq!(
             msg,
             Some(VkMessage {
                 text: String::new(),
                 from_id: 1010,
-                attachments: vec![VkPhoto("$med_url".into())],
+                peer_id: 1000,
+                id: 1,
+                conversation_message_id: 1,
+                date: 1581965623,
+                attachments: vec![VkAttachment::Wall {
+                    owner_id: -1111,
+                    id: 1,
+                    text: "post text here1".into(),
+                    attachments: vec![VkAttachment::Photo(photo(&[
+                        (130, "$med_url"),
+                        (604, "$x_url"),
+                        (807, "$800_url"),
+                        (1080, "$1080_url")
+                    ]))]
+                }],
                 forwarded: vec![],
-                reply_to: None
+                reply_to: None,
+                payload: None
             })
         );
     }
 
     #[test]
     fn test_parse_forwarded_attachment() {
-        let vk = VkApi {
-            client: crate::vkapi::http::TestClient::new("long_poll_fwd_attachment.json"),
-            token: "token".into(),
-            community_name: "sample_community".into(),
-            community_id: "1001".into(),
-        };
+        let vk = VkApi::with_fixture("long_poll_fwd_attachment.json");
         let mut msg: Option<VkMessage> = None;
         VkLongPoll {
             api: &vk,
@@ -263,23 +447,180 @@ mod tests {
                 ts: "100".into(),
             },
         }
-        .poll_once(|m| msg = Some(m))
+        .poll_once(|event| {
+            if let VkEvent::MessageNew(m) = event {
+                msg = Some(m)
+            }
+        })
         .unwrap();
         assert_eq!(
             msg,
             Some(VkMessage {
                 text: "hey check this out".into(),
                 from_id: 1010,
+                peer_id: 1000,
+                id: 2,
+                conversation_message_id: 2,
+                date: 1580239358,
                 attachments: vec![],
                 forwarded: vec![VkMessage {
                     text: "forwarded text".into(),
                     from_id: 1020,
-                    attachments: vec![VkPhoto("$med_url".into())],
+                    peer_id: 1000,
+                    id: 1,
+                    conversation_message_id: 1,
+                    date: 1580239336,
+                    attachments: vec![
+                        VkAttachment::Photo(photo(&[
+                            (130, "$med_url"),
+                            (604, "$x_url"),
+                            (807, "$800_url"),
+                            (1080, "$1080_url"),
+                            (1903, "$2560_url")
+                        ])),
+                        VkAttachment::Video {
+                            owner_id: 1000,
+                            id: 200,
+                            title: "forwarded video".into(),
+                            duration: 42
+                        }
+                    ],
                     forwarded: vec![],
-                    reply_to: None
+                    reply_to: None,
+                    payload: None
                 }],
-                reply_to: None
+                reply_to: None,
+                payload: None
             })
         );
     }
+
+    #[test]
+    fn test_parse_sticker_and_graffiti() {
+        let vk = VkApi::with_fixture("long_poll_sticker_graffiti.json");
+        let mut msg: Option<VkMessage> = None;
+        VkLongPoll {
+            api: &vk,
+            state: VkLongPollState {
+                key: "long_poll_key".into(),
+                server: "https://long_poll_server".into(),
+                ts: "100".into(),
+            },
+        }
+        .poll_once(|event| {
+            if let VkEvent::MessageNew(m) = event {
+                msg = Some(m)
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            msg.unwrap().attachments,
+            vec![
+                VkAttachment::Sticker {
+                    id: 1,
+                    image: Some(photo(&[(64, "$sticker_64_url"), (256, "$sticker_256_url")]))
+                },
+                VkAttachment::Graffiti(photo(&[(720, "$graffiti_url")]))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_attachment_types() {
+        let vk = VkApi::with_fixture("long_poll_attachment_types.json");
+        let mut msg: Option<VkMessage> = None;
+        VkLongPoll {
+            api: &vk,
+            state: VkLongPollState {
+                key: "long_poll_key".into(),
+                server: "https://long_poll_server".into(),
+                ts: "100".into(),
+            },
+        }
+        .poll_once(|event| {
+            if let VkEvent::MessageNew(m) = event {
+                msg = Some(m)
+            }
+        })
+        .unwrap();
+        let msg = msg.unwrap();
+        assert_eq!(
+            msg.attachments,
+            vec![
+                VkAttachment::AudioMessage {
+                    duration: 5,
+                    link_ogg: "$ogg_url".into(),
+                    link_mp3: "$mp3_url".into()
+                },
+                VkAttachment::Link {
+                    url: "https://example.com".into(),
+                    title: "link title".into()
+                },
+                VkAttachment::Poll {
+                    id: 300,
+                    question: "which one?".into(),
+                    answers: vec!["this".into(), "that".into()]
+                },
+                VkAttachment::Doc {
+                    title: "notes.txt".into(),
+                    ext: "txt".into(),
+                    url: "$doc_url".into(),
+                    preview: None
+                },
+                VkAttachment::Geo {
+                    latitude: 59.9398,
+                    longitude: 30.3146,
+                    place: Some("Palace Square".into())
+                }
+            ]
+        );
+        assert!(msg.all_photos().is_empty());
+        assert_eq!(msg.command(), Some("share_location"));
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let vk = VkApi::with_fixture("long_poll_events.json");
+        let mut events = Vec::new();
+        VkLongPoll {
+            api: &vk,
+            state: VkLongPollState {
+                key: "long_poll_key".into(),
+                server: "https://long_poll_server".into(),
+                ts: "100".into(),
+            },
+        }
+        .poll_once(|e| events.push(e))
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                VkEvent::MessageEvent(VkMessageEvent {
+                    event_id: "3a7f".into(),
+                    user_id: 1010,
+                    peer_id: 1010,
+                    payload: Some(serde_json::json!({ "command": "hint" }))
+                }),
+                VkEvent::MessageEdit(VkMessage {
+                    text: "fixed typo".into(),
+                    from_id: 1010,
+                    peer_id: 1010,
+                    id: 12,
+                    conversation_message_id: 8,
+                    date: 1600000000,
+                    attachments: vec![],
+                    forwarded: vec![],
+                    reply_to: None,
+                    payload: None
+                }),
+                VkEvent::MessageAllow { user_id: 1020 },
+                VkEvent::MessageDeny { user_id: 1030 },
+                VkEvent::GroupJoin { user_id: 1040 },
+                VkEvent::GroupLeave { user_id: 1050 }
+            ]
+        );
+        if let VkEvent::MessageEvent(ref event) = events[0] {
+            assert_eq!(event.command(), Some("hint"));
+        }
+    }
 }
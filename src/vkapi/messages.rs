@@ -1,28 +1,278 @@
-use crate::vkapi::{Client, VkApi};
-use crate::BotResult;
+use crate::vkapi::{Client, VkApi, VkEventAction, VkKeyboard, VkMessage, VkMessageEvent};
+use crate::{BotError, BotResult};
+use serde_derive::Deserialize;
+use serde_json::json;
+
+/// How a reply refers to the message it answers
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VkQuote {
+    /// The reply quotes the message, as if the reply button was pressed
+    Reply,
+    /// The message is attached to the reply as forwarded
+    Forward,
+}
 
 pub trait VkMessagesApi {
-    fn send(&self, peer_id: i64, text: &str, attachment: Option<&str>) -> BotResult<()>;
+    fn send(
+        &self,
+        peer_id: i64,
+        text: &str,
+        attachment: Option<&str>,
+        keyboard: Option<&VkKeyboard>,
+    ) -> BotResult<()>;
+
+    /// Sends the text to the conversation the message came from,
+    /// which is a group chat rather than the sender if the message was sent there.
+    /// Repeating a reply with the same text to the same message does not send it again
+    fn reply(
+        &self,
+        msg: &VkMessage,
+        text: &str,
+        attachment: Option<&str>,
+        keyboard: Option<&VkKeyboard>,
+        quote: Option<VkQuote>,
+    ) -> BotResult<()>;
+
+    /// Stops the loading indicator on the pressed button, optionally showing a snackbar
+    /// or opening a link instead of sending a message
+    fn send_event_answer(
+        &self,
+        event: &VkMessageEvent,
+        action: Option<&VkEventAction>,
+    ) -> BotResult<()>;
 }
 
 impl<C: Client> VkMessagesApi for VkApi<C> {
-    fn send(&self, peer_id: i64, text: &str, attachment: Option<&str>) -> BotResult<()> {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let time_now = SystemTime::now().duration_since(UNIX_EPOCH)?;
-        let random_id = time_now.as_millis().to_string();
-        let resp: serde_json::Value = self.call_api(
-            "messages.send",
-            &[
-                ("peer_id", &peer_id.to_string()),
-                ("message", text),
-                ("random_id", &random_id),
-                ("attachment", attachment.unwrap_or_default()),
-            ],
-            None,
-        )?;
-        match resp.get("error") {
-            Some(e) => Err(format!("messages.send returned an error: {}", e).into()),
-            _ => Ok(()),
+    fn send(
+        &self,
+        peer_id: i64,
+        text: &str,
+        attachment: Option<&str>,
+        keyboard: Option<&VkKeyboard>,
+    ) -> BotResult<()> {
+        let random_id = unique_random_id()?;
+        send_message(self, peer_id, random_id, text, attachment, keyboard, None)
+    }
+
+    fn reply(
+        &self,
+        msg: &VkMessage,
+        text: &str,
+        attachment: Option<&str>,
+        keyboard: Option<&VkKeyboard>,
+        quote: Option<VkQuote>,
+    ) -> BotResult<()> {
+        // Messages are referred to by conversation_message_id, since their ids are 0
+        // in group chats where the community cannot read all messages
+        let forward = quote
+            .filter(|_| msg.conversation_message_id != 0)
+            .map(|quote| {
+                json!({
+                    "peer_id": msg.peer_id,
+                    "conversation_message_ids": [msg.conversation_message_id],
+                    "is_reply": quote == VkQuote::Reply,
+                })
+                .to_string()
+            });
+        let random_id = match msg.conversation_message_id {
+            0 => unique_random_id()?,
+            _ => reply_random_id(msg, text),
+        };
+        let result = send_message(
+            self,
+            msg.peer_id,
+            random_id,
+            text,
+            attachment,
+            keyboard,
+            forward.as_deref(),
+        );
+        match result {
+            // Communities without admin rights in a group chat cannot refer to its messages
+            Err(BotError::VkApi(ref e))
+                if forward.is_some() && !e.is_auth() && !e.is_transient() =>
+            {
+                eprintln!("Unable to quote a message in {}: {}", msg.peer_id, e);
+                send_message(
+                    self,
+                    msg.peer_id,
+                    random_id,
+                    text,
+                    attachment,
+                    keyboard,
+                    None,
+                )
+            }
+            result => result,
+        }
+    }
+
+    fn send_event_answer(
+        &self,
+        event: &VkMessageEvent,
+        action: Option<&VkEventAction>,
+    ) -> BotResult<()> {
+        let user_id = event.user_id.to_string();
+        let peer_id = event.peer_id.to_string();
+        let event_data = action.map(|a| a.to_json());
+        let mut params = vec![
+            ("event_id", event.event_id.as_str()),
+            ("user_id", &user_id),
+            ("peer_id", &peer_id),
+        ];
+        if let Some(ref event_data) = event_data {
+            params.push(("event_data", event_data));
+        }
+        let _: serde_json::Value =
+            self.call_api("messages.sendMessageEventAnswer", &params, Some("response"))?;
+        Ok(())
+    }
+}
+
+fn unique_random_id() -> BotResult<i64> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let time_now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    Ok(time_now.as_millis() as i64)
+}
+
+/// VK drops messages whose `random_id` has already been used in the conversation, so deriving it
+/// from the message being answered and the reply text keeps a retried handler from sending
+/// the same reply twice
+fn reply_random_id(msg: &VkMessage, text: &str) -> i64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    let mut hasher = DefaultHasher::new();
+    (msg.peer_id, msg.conversation_message_id, text).hash(&mut hasher);
+    // VK expects a positive int32
+    (hasher.finish() % i32::max_value() as u64) as i64 + 1
+}
+
+fn send_message<C: Client>(
+    vk: &VkApi<C>,
+    peer_id: i64,
+    random_id: i64,
+    text: &str,
+    attachment: Option<&str>,
+    keyboard: Option<&VkKeyboard>,
+    forward: Option<&str>,
+) -> BotResult<()> {
+    let random_id = random_id.to_string();
+    let peer_id = peer_id.to_string();
+    let keyboard = keyboard.map(|k| k.to_json());
+    let mut params = vec![
+        ("peer_id", peer_id.as_str()),
+        ("message", text),
+        ("random_id", &random_id),
+        ("attachment", attachment.unwrap_or_default()),
+    ];
+    if let Some(ref keyboard) = keyboard {
+        params.push(("keyboard", keyboard));
+    }
+    if let Some(forward) = forward {
+        params.push(("forward", forward));
+    }
+    let _: serde_json::Value = vk.call_api("messages.send", &params, Some("response"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_retries_flood_control() {
+        let vk = VkApi::with_fixture("messages_send_flood_control.json");
+        vk.send(1010, "hello", None, None).unwrap();
+    }
+
+    #[test]
+    fn test_send_keyboard() {
+        use crate::vkapi::VkButtonColor;
+        let vk = VkApi::with_fixture("messages_send_keyboard.json");
+        let keyboard = VkKeyboard::inline().text("Да", "yes", VkButtonColor::Primary);
+        vk.send(1010, "hello", None, Some(&keyboard)).unwrap();
+    }
+
+    #[test]
+    fn test_reply() {
+        let vk = VkApi::with_fixture("messages_reply.json");
+        let msg = VkMessage {
+            text: "hello".into(),
+            from_id: 1010,
+            peer_id: 2_000_000_001,
+            id: 0,
+            conversation_message_id: 5,
+            date: 1_600_000_000,
+            attachments: vec![],
+            forwarded: vec![],
+            reply_to: None,
+            payload: None,
+        };
+        vk.reply(&msg, "to the chat", None, None, None).unwrap();
+        vk.reply(&msg, "quoted", None, None, Some(VkQuote::Reply))
+            .unwrap();
+        vk.reply(&msg, "forwarded", None, None, Some(VkQuote::Forward))
+            .unwrap();
+        // the fixture rejects the next quote
+        vk.reply(&msg, "quoted", None, None, Some(VkQuote::Reply))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reply_random_id() {
+        let msg = VkMessage {
+            text: "hello".into(),
+            from_id: 1010,
+            peer_id: 2_000_000_001,
+            id: 0,
+            conversation_message_id: 5,
+            date: 1_600_000_000,
+            attachments: vec![],
+            forwarded: vec![],
+            reply_to: None,
+            payload: None,
+        };
+        let id = reply_random_id(&msg, "reply");
+        assert_eq!(reply_random_id(&msg, "reply"), id);
+        assert_ne!(reply_random_id(&msg, "another reply"), id);
+        let next = VkMessage {
+            conversation_message_id: 6,
+            ..msg
+        };
+        assert_ne!(reply_random_id(&next, "reply"), id);
+        assert!(id > 0 && id <= i32::max_value() as i64);
+    }
+
+    #[test]
+    fn test_send_event_answer() {
+        let vk = VkApi::with_fixture("messages_send_event_answer.json");
+        let event = VkMessageEvent {
+            event_id: "e1".into(),
+            user_id: 1010,
+            peer_id: 1010,
+            payload: None,
+        };
+        vk.send_event_answer(&event, None).unwrap();
+        let snackbar = VkEventAction::ShowSnackbar {
+            text: "hello".into(),
+        };
+        vk.send_event_answer(&event, Some(&snackbar)).unwrap();
+    }
+
+    #[test]
+    fn test_send_auth_failed() {
+        let vk = VkApi::with_fixture("messages_send_auth_failed.json");
+        match vk.send(1010, "hello", None, None) {
+            Err(BotError::VkApi(e)) => {
+                assert!(e.is_auth());
+                assert_eq!(
+                    e.to_string(),
+                    "VK API error 5: User authorization failed: invalid access_token (4). \
+                     (request: method=messages.send, oauth=1)"
+                );
+            }
+            r => panic!("Expected an auth error, got {:?}", r),
         }
     }
 }
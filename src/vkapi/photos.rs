@@ -3,7 +3,8 @@ use crate::BotResult;
 use serde_derive::Deserialize;
 
 pub trait VkPhotosApi {
-    fn download_photo(&self, photo: &VkPhoto) -> BotResult<Vec<u8>>;
+    /// Downloads the smallest size that is at least `min_width` pixels wide (or the largest one)
+    fn download_photo(&self, photo: &VkPhoto, min_width: u64) -> BotResult<Vec<u8>>;
     fn upload_message_photo(&self, peer_id: i64, photo: (&[u8], &str)) -> BotResult<String>;
 }
 
@@ -15,8 +16,8 @@ pub struct VkPhotoUploadResponse {
 }
 
 impl<C: Client> VkPhotosApi for VkApi<C> {
-    fn download_photo(&self, photo: &VkPhoto) -> BotResult<Vec<u8>> {
-        self.client.fetch(&photo.0, &[], &[], None)
+    fn download_photo(&self, photo: &VkPhoto, min_width: u64) -> BotResult<Vec<u8>> {
+        self.client.fetch(photo.url(min_width), &[], &[], None)
     }
 
     fn upload_message_photo(&self, peer_id: i64, photo: (&[u8], &str)) -> BotResult<String> {
@@ -1,19 +1,229 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Peer ids of group chats start here
+pub const GROUP_CHAT_PEER_OFFSET: i64 = 2_000_000_000;
+
+lazy_static! {
+    /// A mention of any community, capturing its id
+    static ref MENTION: Regex =
+        Regex::new(r"^\s*(?:\[(?:club|public)(\d+)\|[^\]]*\]|@(?:club|public)(\d+)\b)[\s,]*")
+            .unwrap();
+}
+
+/// An update delivered by long polling or the Callback API.
+/// Updates of other types are skipped when parsing.
+#[derive(Debug, PartialEq)]
+pub enum VkEvent {
+    MessageNew(VkMessage),
+    /// A callback button was pressed
+    MessageEvent(VkMessageEvent),
+    MessageEdit(VkMessage),
+    /// The user allowed the community to send them messages
+    MessageAllow {
+        user_id: i64,
+    },
+    MessageDeny {
+        user_id: i64,
+    },
+    /// The user joined the community or sent a request to join it
+    GroupJoin {
+        user_id: i64,
+    },
+    GroupLeave {
+        user_id: i64,
+    },
+}
+
+/// A press of a callback button. The button keeps spinning until the event is answered
+/// with `VkMessagesApi::send_event_answer`.
+#[derive(Debug, PartialEq)]
+pub struct VkMessageEvent {
+    pub event_id: String,
+    pub user_id: i64,
+    pub peer_id: i64,
+    pub payload: Option<serde_json::Value>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct VkMessage {
     pub text: String,
     pub from_id: i64,
-    pub attachments: Vec<VkPhoto>,
+    /// The conversation the message was sent to: the sender for private messages,
+    /// 2000000000 + chat id for group chats. 0 for forwarded messages.
+    pub peer_id: i64,
+    /// 0 in group chats where the community cannot read all messages, and for forwarded messages
+    pub id: i64,
+    /// The number of the message within its conversation
+    pub conversation_message_id: i64,
+    /// Unix time
+    pub date: u64,
+    pub attachments: Vec<VkAttachment>,
     pub forwarded: Vec<VkMessage>,
     pub reply_to: Option<Box<VkMessage>>,
+    /// Sent by keyboard buttons, see `VkKeyboard`
+    pub payload: Option<serde_json::Value>,
+}
+
+/// Anything attached to a message, with the fields the bot may need.
+/// Attachments of other types are skipped when parsing.
+#[derive(Debug, PartialEq)]
+pub enum VkAttachment {
+    Photo(VkPhoto),
+    /// A file; images and GIFs come with a preview
+    Doc {
+        title: String,
+        ext: String,
+        url: String,
+        preview: Option<VkPhoto>,
+    },
+    /// A voice message; `duration` is in seconds
+    AudioMessage {
+        duration: u64,
+        link_ogg: String,
+        link_mp3: String,
+    },
+    Sticker {
+        id: i64,
+        image: Option<VkPhoto>,
+    },
+    Graffiti(VkPhoto),
+    /// A location shared by the sender. VK sends it separately from the attachments,
+    /// in the `geo` field of the message.
+    Geo {
+        latitude: f64,
+        longitude: f64,
+        place: Option<String>,
+    },
+    Link {
+        url: String,
+        title: String,
+    },
+    /// A wall post, with its own attachments
+    Wall {
+        owner_id: i64,
+        id: i64,
+        text: String,
+        attachments: Vec<VkAttachment>,
+    },
+    Video {
+        owner_id: i64,
+        id: i64,
+        title: String,
+        duration: u64,
+    },
+    Poll {
+        id: i64,
+        question: String,
+        answers: Vec<String>,
+    },
+}
+
+/// An image attached to a message, available in several sizes
+#[derive(Debug, PartialEq)]
+pub struct VkPhoto {
+    /// Sorted by width, smallest first; never empty
+    pub sizes: Vec<VkPhotoSize>,
 }
 
 #[derive(Debug, PartialEq)]
-pub struct VkPhoto(pub String);
+pub struct VkPhotoSize {
+    pub width: u64,
+    pub url: String,
+}
+
+impl VkPhoto {
+    /// Returns `None` if there are no sizes to choose from
+    pub fn new(mut sizes: Vec<VkPhotoSize>) -> Option<Self> {
+        if sizes.is_empty() {
+            return None;
+        }
+        sizes.sort_by_key(|s| s.width);
+        Some(Self { sizes })
+    }
+
+    /// The URL of the smallest size at least `min_width` pixels wide, or of the largest size
+    /// if there are none
+    pub fn url(&self, min_width: u64) -> &str {
+        let size = self
+            .sizes
+            .iter()
+            .find(|s| s.width >= min_width)
+            .unwrap_or_else(|| self.sizes.last().unwrap());
+        &size.url
+    }
+}
+
+impl VkAttachment {
+    /// Images that can be matched against quest targets: photos, document previews,
+    /// stickers, graffiti, and the images in wall posts
+    pub fn photos(&self) -> Vec<&VkPhoto> {
+        match self {
+            VkAttachment::Photo(photo) | VkAttachment::Graffiti(photo) => vec![photo],
+            VkAttachment::Doc { preview, .. } => preview.iter().collect(),
+            VkAttachment::Sticker { image, .. } => image.iter().collect(),
+            VkAttachment::Wall { attachments, .. } => {
+                attachments.iter().flat_map(|a| a.photos()).collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl VkEvent {
+    /// The conversation of the message or button press, or the user for other events
+    pub fn peer_id(&self) -> i64 {
+        match self {
+            VkEvent::MessageNew(msg) | VkEvent::MessageEdit(msg) => msg.peer_id,
+            VkEvent::MessageEvent(event) => event.peer_id,
+            VkEvent::MessageAllow { user_id }
+            | VkEvent::MessageDeny { user_id }
+            | VkEvent::GroupJoin { user_id }
+            | VkEvent::GroupLeave { user_id } => *user_id,
+        }
+    }
+}
+
+impl VkMessageEvent {
+    /// The command of the button that was pressed
+    pub fn command(&self) -> Option<&str> {
+        self.payload.as_ref()?.get("command")?.as_str()
+    }
+}
 
 impl VkMessage {
-    pub fn all_attachments(&self) -> Vec<&VkPhoto> {
+    pub fn is_group_chat(&self) -> bool {
+        self.peer_id >= GROUP_CHAT_PEER_OFFSET
+    }
+
+    /// Removes the mention of the community the message starts with, if there is one.
+    /// Mentions look like `[club1|@salmonbot]`, or `@club1` when typed by hand.
+    pub fn strip_mention(&mut self, community_id: &str) -> bool {
+        let mention = match MENTION.captures(&self.text) {
+            Some(mention) => mention,
+            None => return false,
+        };
+        let id = mention
+            .get(1)
+            .or_else(|| mention.get(2))
+            .map(|id| id.as_str());
+        if id != Some(community_id) {
+            return false;
+        }
+        let end = mention.get(0).unwrap().end();
+        self.text = self.text[end..].to_owned();
+        true
+    }
+
+    /// The command of the keyboard button the message was sent with
+    pub fn command(&self) -> Option<&str> {
+        self.payload.as_ref()?.get("command")?.as_str()
+    }
+
+    /// Attachments of the message and of the messages it forwards or replies to
+    pub fn all_attachments(&self) -> Vec<&VkAttachment> {
         let mut attachments = Vec::new();
-        fn append_attachments<'a>(msg: &'a VkMessage, atts: &mut Vec<&'a VkPhoto>) {
+        fn append_attachments<'a>(msg: &'a VkMessage, atts: &mut Vec<&'a VkAttachment>) {
             atts.extend(msg.attachments.iter());
             for fwd in msg.forwarded.iter() {
                 append_attachments(fwd, atts);
@@ -25,40 +235,128 @@ impl VkMessage {
         append_attachments(self, &mut attachments);
         attachments
     }
+
+    /// Images among `all_attachments`, see `VkAttachment::photos`
+    pub fn all_photos(&self) -> Vec<&VkPhoto> {
+        self.all_attachments()
+            .into_iter()
+            .flat_map(|a| a.photos())
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn photo(url: &str) -> VkPhoto {
+        VkPhoto::new(vec![VkPhotoSize {
+            width: 100,
+            url: url.into(),
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_photo_url() {
+        let size = |width, url: &str| VkPhotoSize {
+            width,
+            url: url.into(),
+        };
+        let photo = VkPhoto::new(vec![size(807, "y"), size(130, "m"), size(604, "x")]).unwrap();
+        assert_eq!(photo.url(0), "m");
+        assert_eq!(photo.url(300), "x");
+        assert_eq!(photo.url(604), "x");
+        assert_eq!(photo.url(2000), "y");
+        assert_eq!(VkPhoto::new(vec![]), None);
+    }
+
     #[test]
     fn test_all_attachments() {
         let msg = VkMessage {
             text: String::new(),
             from_id: 0,
-            attachments: vec![VkPhoto("$outer".into())],
+            peer_id: 0,
+            id: 0,
+            conversation_message_id: 0,
+            date: 0,
+            attachments: vec![
+                VkAttachment::Photo(photo("$outer")),
+                VkAttachment::AudioMessage {
+                    duration: 3,
+                    link_ogg: "$ogg".into(),
+                    link_mp3: "$mp3".into(),
+                },
+            ],
             forwarded: vec![VkMessage {
                 text: String::new(),
                 from_id: 1,
-                attachments: vec![VkPhoto("$inner".into())],
+                peer_id: 0,
+                id: 0,
+                conversation_message_id: 0,
+                date: 0,
+                attachments: vec![VkAttachment::Wall {
+                    owner_id: -1,
+                    id: 1,
+                    text: String::new(),
+                    attachments: vec![VkAttachment::Photo(photo("$inner"))],
+                }],
                 forwarded: vec![],
                 reply_to: Some(Box::new(VkMessage {
                     text: String::new(),
                     from_id: 2,
-                    attachments: vec![VkPhoto("$inner_reply".into())],
+                    peer_id: 0,
+                    id: 0,
+                    conversation_message_id: 0,
+                    date: 0,
+                    attachments: vec![VkAttachment::Sticker {
+                        id: 1,
+                        image: Some(photo("$inner_reply")),
+                    }],
                     forwarded: vec![],
                     reply_to: None,
+                    payload: None,
                 })),
+                payload: None,
             }],
             reply_to: None,
+            payload: None,
         };
+        assert_eq!(msg.all_attachments().len(), 4);
         assert_eq!(
-            msg.all_attachments(),
-            vec![
-                &VkPhoto("$outer".into()),
-                &VkPhoto("$inner".into()),
-                &VkPhoto("$inner_reply".into())
-            ]
+            msg.all_photos(),
+            vec![&photo("$outer"), &photo("$inner"), &photo("$inner_reply")]
         )
     }
+
+    #[test]
+    fn test_strip_mention() {
+        let mut msg = VkMessage {
+            text: String::new(),
+            from_id: 1010,
+            peer_id: 2_000_000_001,
+            id: 0,
+            conversation_message_id: 5,
+            date: 0,
+            attachments: vec![],
+            forwarded: vec![],
+            reply_to: None,
+            payload: None,
+        };
+        assert!(msg.is_group_chat());
+        for (text, stripped) in &[
+            ("[club1001|@salmonbot], лосось", Some("лосось")),
+            ("[public1001|Лосось] лосось", Some("лосось")),
+            ("@club1001 лосось", Some("лосось")),
+            ("[club1001|@salmonbot]", Some("")),
+            ("[club10012|@other] лосось", None),
+            ("@club10012 лосось", None),
+            ("[id1001|Лосось] лосось", None),
+            ("лосось [club1001|@salmonbot]", None),
+        ] {
+            msg.text = text.to_string();
+            assert_eq!(msg.strip_mention("1001"), stripped.is_some(), "{}", text);
+            assert_eq!(msg.text, stripped.unwrap_or(text), "{}", text);
+        }
+    }
 }
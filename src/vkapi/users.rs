@@ -1,8 +1,9 @@
+use crate::vkapi::error::VK_INVALID_USER_ID;
 use crate::vkapi::{Client, VkApi};
-use crate::BotResult;
+use crate::{BotError, BotResult};
 use serde_derive::Deserialize;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct VkUser {
     pub id: i64,
     pub screen_name: String,
@@ -26,14 +27,18 @@ pub trait VkUsersApi {
 
 impl<C: Client> VkUsersApi for VkApi<C> {
     fn get_user(&self, screen_name: &str) -> BotResult<Option<VkUser>> {
-        let mut response: serde_json::Value = self.call_api(
+        let response = self.call_api::<serde_json::Value>(
             "users.get",
             &[("user_ids", screen_name), ("fields", "screen_name")],
             None,
-        )?;
+        );
+        let mut response = match response {
+            Err(BotError::VkApi(ref e)) if e.code == VK_INVALID_USER_ID => return Ok(None),
+            r => r?,
+        };
         if let Some(users) = response.get_mut("response").and_then(|r| r.as_array_mut()) {
             if users.len() == 1 {
-                return serde_json::from_value(users.remove(0)).map_err(|e| e.into());
+                return serde_json::from_value(users.remove(0)).map_err(BotError::from);
             }
         }
         Ok(None)
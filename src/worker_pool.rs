@@ -0,0 +1,159 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TryRecvError};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of worker threads, each with a bounded queue.
+///
+/// Jobs are assigned to workers by key, so jobs submitted with the same key
+/// (e.g. the sender's id) run one after another in submission order.
+/// When a worker's queue is full, `submit` blocks until there is room.
+pub struct WorkerPool {
+    queues: Vec<SyncSender<Job>>,
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, queue_capacity: usize) -> Self {
+        let queues = (0..workers.max(1))
+            .map(|i| {
+                let (tx, rx) = sync_channel::<Job>(queue_capacity);
+                thread::Builder::new()
+                    .name(format!("worker-{}", i))
+                    .spawn(move || {
+                        for job in rx.iter() {
+                            // A panicking job shouldn't take down the jobs queued after it
+                            if catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                eprintln!("Worker {} recovered from a panic", i);
+                            }
+                        }
+                    })
+                    .expect("Failed to spawn a worker thread");
+                tx
+            })
+            .collect();
+        Self { queues }
+    }
+
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, key: i64, job: F) {
+        let worker = (key as u64 % self.queues.len() as u64) as usize;
+        // Workers never exit while the pool is alive, so the receiver can't be gone
+        self.queues[worker]
+            .send(Box::new(job))
+            .expect("Worker thread is not running");
+    }
+}
+
+/// A group of jobs whose completion can be checked, such as the messages of one long poll response
+pub struct Batch {
+    tx: Sender<()>,
+    rx: Receiver<()>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        Self { tx, rx }
+    }
+
+    /// Wraps the job so that the batch is not done until it has run (or panicked)
+    pub fn track<F: FnOnce() + Send + 'static>(&self, job: F) -> impl FnOnce() + Send + 'static {
+        // Nothing is ever sent, the receiver only learns when the last sender is dropped
+        let token = self.tx.clone();
+        move || {
+            let _token = token;
+            job()
+        }
+    }
+
+    /// Stops tracking new jobs, so that the batch can be done once the tracked ones have run
+    pub fn seal(self) -> SealedBatch {
+        SealedBatch { rx: self.rx }
+    }
+}
+
+pub struct SealedBatch {
+    rx: Receiver<()>,
+}
+
+impl SealedBatch {
+    /// Does not block: the jobs keep running on the workers
+    pub fn is_done(&self) -> bool {
+        match self.rx.try_recv() {
+            Err(TryRecvError::Disconnected) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_same_key_ordering() {
+        let pool = WorkerPool::new(4, 2);
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let (done_tx, done_rx) = channel();
+        for i in 0..20 {
+            let processed = processed.clone();
+            let done_tx = done_tx.clone();
+            pool.submit(7, move || {
+                if i % 3 == 0 {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                processed.lock().unwrap().push(i);
+                done_tx.send(()).unwrap();
+            });
+        }
+        for _ in 0..20 {
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(*processed.lock().unwrap(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_survives_panics() {
+        let pool = WorkerPool::new(1, 1);
+        let (done_tx, done_rx) = channel();
+        pool.submit(1, || panic!("oops"));
+        pool.submit(1, move || done_tx.send(()).unwrap());
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_batch_done() {
+        let pool = WorkerPool::new(4, 2);
+        let processed = Arc::new(Mutex::new(Vec::new()));
+        let batch = Batch::new();
+        let (release_tx, release_rx) = channel::<()>();
+        let release_rx = Arc::new(Mutex::new(release_rx));
+        for i in 0..8 {
+            let release_rx = release_rx.clone();
+            let processed = processed.clone();
+            pool.submit(
+                i,
+                batch.track(move || {
+                    let _ = release_rx.lock().unwrap().recv();
+                    if i == 3 {
+                        panic!("oops");
+                    }
+                    processed.lock().unwrap().push(i);
+                }),
+            );
+        }
+        let batch = batch.seal();
+        assert!(!batch.is_done());
+        drop(release_tx);
+        for _ in 0..500 {
+            if batch.is_done() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(batch.is_done());
+        assert_eq!(processed.lock().unwrap().len(), 7);
+    }
+}